@@ -1,5 +1,29 @@
 // generated by `sqlx migrate build-script`
+use std::path::Path;
+use std::process::Command;
+
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
+
+    // commit of the build, e.g. a Docker build argument, or of the working copy
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs/heads");
+    }
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+        });
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=GIT_COMMIT={commit}");
+    }
 }
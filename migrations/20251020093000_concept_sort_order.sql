@@ -0,0 +1,3 @@
+alter table concepts
+    add column if not exists sort_order integer;
+create index if not exists idx_concept_module_id_parent_id on concepts (module_id, parent_id);
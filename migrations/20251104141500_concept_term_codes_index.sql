@@ -0,0 +1 @@
+create index if not exists idx_concept_term_codes on concepts using gin (term_codes jsonb_path_ops);
@@ -0,0 +1,99 @@
+create table if not exists codings
+(
+    id      bigint generated always as identity,
+    system  text not null,
+    code    text not null,
+    version text,
+    display text not null,
+    primary key (id),
+    constraint codings_system_code_version_key unique nulls not distinct (system, code, version)
+);
+create index if not exists idx_coding_code on codings (code);
+
+create table if not exists concept_codings
+(
+    concept_id uuid    not null
+        constraint concept_codings_concepts_id_fk
+            references concepts
+            on delete cascade,
+    coding_id  bigint  not null
+        constraint concept_codings_codings_id_fk
+            references codings,
+    kind       text    not null
+        constraint concept_codings_kind_check
+            check (kind in ('term_code', 'filter_option')),
+    position   integer not null,
+    primary key (concept_id, kind, position)
+);
+create index if not exists idx_concept_coding_coding_id on concept_codings (coding_id);
+
+-- move term codes and filter options, elements without system or code are dropped. Shared codings take the display
+-- of the first concept (by id) naming one.
+with elements as (select c.id, l.kind, l.obj, l.position
+                  from concepts c
+                           cross join lateral (select 'term_code' as kind, t.obj, t.position
+                                               from jsonb_array_elements(case jsonb_typeof(c.term_codes)
+                                                                             when 'array' then c.term_codes
+                                                                             else '[]' end)
+                                                        with ordinality t(obj, position)
+                                               union all
+                                               select 'filter_option', f.obj, f.position
+                                               from jsonb_array_elements(case jsonb_typeof(c.filter_options)
+                                                                             when 'array' then c.filter_options
+                                                                             else '[]' end)
+                                                        with ordinality f(obj, position)) l
+                  where l.obj ->> 'system' is not null
+                    and l.obj ->> 'code' is not null)
+insert into codings (system, code, version, display)
+select distinct on (obj ->> 'system', obj ->> 'code', obj ->> 'version') obj ->> 'system',
+                                                                        obj ->> 'code',
+                                                                        obj ->> 'version',
+                                                                        coalesce(obj ->> 'display', '')
+from elements
+order by obj ->> 'system', obj ->> 'code', obj ->> 'version', obj ->> 'display' is null, id, kind, position
+on conflict do nothing;
+
+insert into concept_codings (concept_id, coding_id, kind, position)
+select c.id, k.id, l.kind, l.position
+from concepts c
+         cross join lateral (select 'term_code' as kind, t.obj, t.position
+                             from jsonb_array_elements(case jsonb_typeof(c.term_codes)
+                                                           when 'array' then c.term_codes
+                                                           else '[]' end) with ordinality t(obj, position)
+                             union all
+                             select 'filter_option', f.obj, f.position
+                             from jsonb_array_elements(case jsonb_typeof(c.filter_options)
+                                                           when 'array' then c.filter_options
+                                                           else '[]' end) with ordinality f(obj, position)) l
+         join codings k on k.system = l.obj ->> 'system' and k.code = l.obj ->> 'code' and
+                           k.version is not distinct from l.obj ->> 'version';
+
+alter table concepts
+    drop column if exists term_codes,
+    drop column if exists filter_options;
+
+-- concepts with their codings as serialized by the API
+create or replace view concepts_with_codings as
+select c.id,
+       c.module_id,
+       c.parent_id,
+       c.display,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', k.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'term_code') as term_codes,
+       c.selectable,
+       c.leaf,
+       c.time_restriction_allowed,
+       c.filter_type,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', k.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'filter_option') as filter_options,
+       c.version,
+       c.sort_order
+from concepts c;
@@ -0,0 +1,21 @@
+create table if not exists code_systems
+(
+    id        uuid    not null,
+    url       text    not null
+        constraint code_systems_url_key unique,
+    name      text    not null,
+    versions  text[]  not null default '{}',
+    publisher text,
+    local     boolean not null default false,
+    primary key (id)
+);
+
+insert into code_systems (id, url, name, versions, publisher, local)
+values (gen_random_uuid(), 'http://loinc.org', 'LOINC', '{}', 'Regenstrief Institute, Inc.', false),
+       (gen_random_uuid(), 'http://snomed.info/sct', 'SNOMED CT', '{}', 'SNOMED International', false),
+       (gen_random_uuid(), 'http://fhir.de/CodeSystem/bfarm/icd-10-gm', 'ICD-10-GM', '{}', 'BfArM', false),
+       (gen_random_uuid(), 'http://fhir.de/CodeSystem/bfarm/ops', 'OPS', '{}', 'BfArM', false),
+       (gen_random_uuid(), 'http://fhir.de/CodeSystem/bfarm/atc', 'ATC', '{}', 'BfArM', false),
+       (gen_random_uuid(), 'https://fhir.diz.uni-marburg.de/CodeSystem/swisslab-code', 'Swisslab', '{}',
+        'DIZ Marburg', true)
+on conflict do nothing;
@@ -0,0 +1,23 @@
+create table if not exists terminologies
+(
+    system      text        not null,
+    version     text        not null,
+    format      text        not null,
+    imported_at timestamptz not null default now(),
+    primary key (system, version)
+);
+
+create table if not exists terminology_codes
+(
+    system  text not null,
+    version text not null,
+    code    text not null,
+    display text not null,
+    status  text not null default 'active',
+    primary key (system, version, code),
+    constraint terminology_codes_terminologies_fk
+        foreign key (system, version) references terminologies
+            on delete cascade
+);
+create index if not exists idx_terminology_code_code on terminology_codes (system, lower(code) text_pattern_ops);
+create index if not exists idx_terminology_code_display on terminology_codes (system, lower(display) text_pattern_ops);
@@ -0,0 +1,44 @@
+create table if not exists concept_designations
+(
+    concept_id uuid    not null
+        constraint concept_designations_concepts_id_fk
+            references concepts
+            on delete cascade,
+    language   text    not null,
+    use        text    not null
+        constraint concept_designations_use_check
+            check (use in ('preferred', 'synonym', 'abbreviation')),
+    value      text    not null,
+    position   integer not null,
+    primary key (concept_id, position)
+);
+
+-- concepts with their codings and designations as serialized by the API
+create or replace view concepts_with_codings as
+select c.id,
+       c.module_id,
+       c.parent_id,
+       c.display,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', k.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'term_code') as term_codes,
+       c.selectable,
+       c.leaf,
+       c.time_restriction_allowed,
+       c.filter_type,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', k.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'filter_option') as filter_options,
+       c.version,
+       c.sort_order,
+       (select jsonb_agg(jsonb_build_object('language', d.language, 'use', d.use, 'value', d.value)
+                         order by d.position)
+        from concept_designations d
+        where d.concept_id = c.id)                  as designations
+from concepts c;
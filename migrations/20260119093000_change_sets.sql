@@ -0,0 +1,30 @@
+create table if not exists change_sets
+(
+    id          uuid        not null,
+    title       text        not null,
+    author      text        not null,
+    status      text        not null default 'open'
+        constraint change_sets_status_check
+            check (status in ('open', 'submitted', 'approved', 'rejected')),
+    reviewer    text,
+    comment     text,
+    created_at  timestamptz not null default now(),
+    reviewed_at timestamptz,
+    primary key (id)
+);
+
+-- concept edits of a change set, as serialized by the API
+create table if not exists concept_drafts
+(
+    change_set_id uuid        not null
+        constraint concept_drafts_change_sets_id_fk
+            references change_sets
+            on delete cascade,
+    concept_id    uuid        not null,
+    module_id     uuid        not null,
+    concept       jsonb       not null,
+    updated_at    timestamptz not null default now(),
+    primary key (change_set_id, concept_id)
+);
+create index if not exists idx_concept_draft_concept_id on concept_drafts (concept_id);
+create index if not exists idx_concept_draft_module_id on concept_drafts (module_id);
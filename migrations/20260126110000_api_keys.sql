@@ -0,0 +1,15 @@
+create table if not exists api_keys
+(
+    id           uuid        not null,
+    name         text        not null,
+    -- leading characters of the key, to recognize it in listings
+    prefix       text        not null,
+    key_hash     text        not null,
+    scopes       text[]      not null,
+    created_at   timestamptz not null default now(),
+    expires_at   timestamptz,
+    last_used_at timestamptz,
+    revoked_at   timestamptz,
+    primary key (id),
+    constraint api_keys_key_hash_key unique (key_hash)
+);
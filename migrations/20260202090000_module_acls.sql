@@ -0,0 +1,8 @@
+-- modules without entries are accessible by all readers and editors
+create table if not exists module_acls
+(
+    module_id  uuid not null references modules (id) on delete cascade,
+    group_name text not null,
+    permission text not null check (permission in ('read', 'write')),
+    primary key (module_id, group_name)
+);
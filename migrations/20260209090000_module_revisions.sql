@@ -0,0 +1,126 @@
+-- revision of the concepts of a module, bumped by each change of its tree (including drafts)
+alter table modules
+    add column if not exists revision bigint not null default 0;
+
+-- locks in id order, concurrent transactions changing several modules don't deadlock
+create or replace function bump_module_revisions(module_ids uuid[]) returns void
+    language sql as
+$$
+update modules m
+set revision = m.revision + 1
+from (select id from modules where id = any (module_ids) order by id for update) l
+where m.id = l.id;
+$$;
+
+-- tables with a module_id column
+create or replace function module_rows_changed() returns trigger
+    language plpgsql as
+$$
+begin
+    if tg_op = 'INSERT' then
+        perform bump_module_revisions(array(select distinct module_id from new_rows));
+    elsif tg_op = 'UPDATE' then
+        perform bump_module_revisions(array(select module_id from new_rows
+                                            union
+                                            select module_id from old_rows));
+    else
+        perform bump_module_revisions(array(select distinct module_id from old_rows));
+    end if;
+    return null;
+end;
+$$;
+
+-- tables with a concept_id column
+create or replace function concept_rows_changed() returns trigger
+    language plpgsql as
+$$
+begin
+    if tg_op = 'INSERT' then
+        perform bump_module_revisions(array(select distinct c.module_id
+                                            from new_rows r join concepts c on c.id = r.concept_id));
+    elsif tg_op = 'UPDATE' then
+        perform bump_module_revisions(array(select c.module_id
+                                            from new_rows r join concepts c on c.id = r.concept_id
+                                            union
+                                            select c.module_id
+                                            from old_rows r join concepts c on c.id = r.concept_id));
+    else
+        -- rows deleted by cascade have no concept anymore, its deletion bumped the revision
+        perform bump_module_revisions(array(select distinct c.module_id
+                                            from old_rows r join concepts c on c.id = r.concept_id));
+    end if;
+    return null;
+end;
+$$;
+
+-- displays of codings are shared by the concepts of all modules
+create or replace function codings_changed() returns trigger
+    language plpgsql as
+$$
+begin
+    perform bump_module_revisions(array(select distinct c.module_id
+                                        from new_rows r
+                                                 join concept_codings cc on cc.coding_id = r.id
+                                                 join concepts c on c.id = cc.concept_id));
+    return null;
+end;
+$$;
+
+-- drafts are shown while their change set is open or submitted
+create or replace function change_sets_changed() returns trigger
+    language plpgsql as
+$$
+begin
+    perform bump_module_revisions(array(select distinct d.module_id
+                                        from new_rows r join concept_drafts d on d.change_set_id = r.id));
+    return null;
+end;
+$$;
+
+create or replace trigger concepts_inserted
+    after insert on concepts referencing new table as new_rows
+    for each statement execute function module_rows_changed();
+create or replace trigger concepts_updated
+    after update on concepts referencing old table as old_rows new table as new_rows
+    for each statement execute function module_rows_changed();
+create or replace trigger concepts_deleted
+    after delete on concepts referencing old table as old_rows
+    for each statement execute function module_rows_changed();
+
+create or replace trigger concept_drafts_inserted
+    after insert on concept_drafts referencing new table as new_rows
+    for each statement execute function module_rows_changed();
+create or replace trigger concept_drafts_updated
+    after update on concept_drafts referencing old table as old_rows new table as new_rows
+    for each statement execute function module_rows_changed();
+create or replace trigger concept_drafts_deleted
+    after delete on concept_drafts referencing old table as old_rows
+    for each statement execute function module_rows_changed();
+
+create or replace trigger concept_codings_inserted
+    after insert on concept_codings referencing new table as new_rows
+    for each statement execute function concept_rows_changed();
+create or replace trigger concept_codings_updated
+    after update on concept_codings referencing old table as old_rows new table as new_rows
+    for each statement execute function concept_rows_changed();
+create or replace trigger concept_codings_deleted
+    after delete on concept_codings referencing old table as old_rows
+    for each statement execute function concept_rows_changed();
+
+create or replace trigger concept_designations_inserted
+    after insert on concept_designations referencing new table as new_rows
+    for each statement execute function concept_rows_changed();
+create or replace trigger concept_designations_updated
+    after update on concept_designations referencing old table as old_rows new table as new_rows
+    for each statement execute function concept_rows_changed();
+create or replace trigger concept_designations_deleted
+    after delete on concept_designations referencing old table as old_rows
+    for each statement execute function concept_rows_changed();
+
+create or replace trigger codings_updated
+    after update on codings referencing new table as new_rows
+    for each statement execute function codings_changed();
+
+create or replace trigger change_sets_updated
+    after update on change_sets referencing new table as new_rows
+    for each statement execute function change_sets_changed();
@@ -0,0 +1,14 @@
+-- notifies listening replicas of changed modules on commit, e.g. to invalidate cached trees.
+-- Notifications of a transaction are sent once per module.
+create or replace function bump_module_revisions(module_ids uuid[]) returns void
+    language plpgsql as
+$$
+begin
+    update modules m
+    set revision = m.revision + 1
+    from (select id from modules where id = any (module_ids) order by id for update) l
+    where m.id = l.id;
+
+    perform pg_notify('module_changes', id::text) from unnest(module_ids) id;
+end;
+$$;
@@ -0,0 +1,4 @@
+-- published concept a draft is based on, JSON null for new concepts. Approving fails if the concept changed since.
+-- Drafts saved before have no base (SQL null) and are not checked.
+alter table concept_drafts
+    add column if not exists base jsonb;
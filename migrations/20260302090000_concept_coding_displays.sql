@@ -0,0 +1,47 @@
+-- displays as saved with each concept. The display of a shared coding is the one it was first saved with.
+alter table concept_codings
+    add column if not exists display text;
+
+update concept_codings cc
+set display = k.display
+from codings k
+where k.id = cc.coding_id
+  and cc.display is null;
+
+alter table concept_codings
+    alter column display set not null;
+
+-- concepts with their codings, designations and metadata as serialized by the API
+create or replace view concepts_with_codings as
+select c.id,
+       c.module_id,
+       c.parent_id,
+       c.display,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', cc.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'term_code') as term_codes,
+       c.selectable,
+       c.leaf,
+       c.time_restriction_allowed,
+       c.filter_type,
+       (select jsonb_agg(jsonb_build_object('code', k.code, 'system', k.system, 'display', cc.display,
+                                            'version', k.version) order by cc.position)
+        from concept_codings cc
+                 join codings k on k.id = cc.coding_id
+        where cc.concept_id = c.id
+          and cc.kind = 'filter_option') as filter_options,
+       c.version,
+       c.sort_order,
+       (select jsonb_agg(jsonb_build_object('language', d.language, 'use', d.use, 'value', d.value)
+                         order by d.position)
+        from concept_designations d
+        where d.concept_id = c.id)                  as designations,
+       c.description,
+       c.definition,
+       c.inclusion_note,
+       c.exclusion_note,
+       c.links
+from concepts c;
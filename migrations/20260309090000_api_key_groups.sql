@@ -0,0 +1,3 @@
+-- groups of machine clients, granting access to restricted modules
+alter table api_keys
+    add column if not exists groups text[] not null default '{}';
@@ -0,0 +1,561 @@
+use crate::auth::{Role, User};
+use crate::error::ApiError;
+use crate::hex;
+use crate::server::ApiContext;
+use anyhow::anyhow;
+use axum::extract::{Path, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::{debug_handler, Json, Router};
+use chrono::{DateTime, Utc};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{FromRow, PgExecutor};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// API key of a machine client, the key itself is only returned on creation
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, Clone)]
+pub(crate) struct ApiKey {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<Scope>,
+    /// groups granting access to restricted modules
+    groups: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "text", rename_all = "kebab-case")]
+pub(crate) enum Scope {
+    Read,
+    WriteConcepts,
+    Admin,
+}
+
+impl Scope {
+    /// Permissions of the scope. Scopes don't include each other, each one allows reading.
+    pub(crate) fn grants(self, role: Role) -> bool {
+        match self {
+            Scope::Read => role == Role::Reader,
+            Scope::WriteConcepts => role <= Role::Editor,
+            Scope::Admin => role == Role::Reader || role == Role::Admin,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewApiKey {
+    name: String,
+    scopes: Vec<Scope>,
+    #[serde(default)]
+    groups: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Created key, the secret `key` cannot be retrieved later
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new()
+        .routes(routes!(all, create))
+        .routes(routes!(revoke))
+        .routes(routes!(rotate))
+}
+
+/// Authenticates requests with an `Authorization: ApiKey <key>` header. Other requests
+/// are passed on unchanged.
+pub(crate) async fn authenticate(
+    State(ctx): State<Arc<ApiContext>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let key = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("ApiKey "))
+        .map(|k| k.trim().to_owned());
+
+    if let Some(key) = key {
+        let user = verify(&ctx.db, &key).await?.ok_or(ApiError(
+            anyhow!("Invalid, expired or revoked API key"),
+            StatusCode::UNAUTHORIZED,
+        ))?;
+        request.extensions_mut().insert(user);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Returns the user of a valid key and updates its last use.
+async fn verify(db: impl PgExecutor<'_> + Copy, key: &str) -> Result<Option<User>, sqlx::Error> {
+    let Some(api_key) = sqlx::query!(
+        r#"select id, name, scopes as "scopes: Vec<Scope>", groups from api_keys
+           where key_hash = $1 and revoked_at is null and (expires_at is null or expires_at > now())"#,
+        hash(key)
+    )
+    .fetch_optional(db)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // last use is updated at most once per minute, not on every request
+    sqlx::query!(
+        r#"update api_keys set last_used_at = now()
+           where id = $1 and (last_used_at is null or last_used_at < now() - interval '1 minute')"#,
+        api_key.id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(Some(User::api_key(
+        &api_key.name,
+        api_key.scopes,
+        api_key.groups,
+    )))
+}
+
+/// Lists the API keys, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "api-keys",
+    responses((status = 200, description = "API keys without their secret", body = Vec<ApiKey>))
+)]
+#[debug_handler]
+async fn all(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    user.require(Role::Admin)?;
+
+    let api_keys = sqlx::query_as!(
+        ApiKey,
+        r#"select id, name, prefix, scopes as "scopes: Vec<Scope>", groups, created_at,
+                  expires_at, last_used_at, revoked_at
+           from api_keys order by created_at desc"#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(api_keys))
+}
+
+/// Creates an API key
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys",
+    tag = "api-keys",
+    request_body = NewApiKey,
+    responses((status = 201, description = "Created key including its secret", body = CreatedApiKey))
+)]
+#[debug_handler]
+async fn create(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    api_key: Json<NewApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), ApiError> {
+    user.require(Role::Admin)?;
+
+    if api_key.scopes.is_empty() {
+        return Err(ApiError(
+            anyhow!("API keys require at least one scope"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let created = insert(&ctx.db, &api_key).await?;
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// Revokes an API key
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{id}",
+    tag = "api-keys",
+    params(("id" = Uuid, Path, description = "API key id")),
+    responses((status = 204, description = "API key revoked"))
+)]
+#[debug_handler]
+async fn revoke(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Role::Admin)?;
+
+    let result = sqlx::query!(
+        r#"update api_keys set revoked_at = now() where id = $1 and revoked_at is null"#,
+        id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    match result.rows_affected() {
+        0 => Err(not_found(id)),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+/// Replaces a key by a new one with the same name, scopes and expiry.
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys/{id}/rotate",
+    tag = "api-keys",
+    params(("id" = Uuid, Path, description = "API key id")),
+    responses((status = 201, description = "New key including its secret", body = CreatedApiKey))
+)]
+#[debug_handler]
+async fn rotate(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), ApiError> {
+    user.require(Role::Admin)?;
+
+    let mut tx = ctx.db.begin().await?;
+    let old = sqlx::query_as!(
+        NewApiKey,
+        r#"update api_keys set revoked_at = now() where id = $1 and revoked_at is null
+           returning name, scopes as "scopes: Vec<Scope>", groups, expires_at"#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| not_found(id))?;
+    let created = insert(&mut *tx, &old).await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+async fn insert(db: impl PgExecutor<'_>, api_key: &NewApiKey) -> Result<CreatedApiKey, ApiError> {
+    let key = generate()?;
+
+    let created = sqlx::query_as!(
+        ApiKey,
+        r#"insert into api_keys (id, name, prefix, key_hash, scopes, groups, expires_at)
+           values ($1, $2, $3, $4, $5, $6, $7)
+           returning id, name, prefix, scopes as "scopes: Vec<Scope>", groups, created_at,
+                     expires_at, last_used_at, revoked_at"#,
+        Uuid::new_v4(),
+        api_key.name,
+        &key[..12],
+        hash(&key),
+        &api_key.scopes as &[Scope],
+        &api_key.groups,
+        api_key.expires_at
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(CreatedApiKey {
+        key,
+        api_key: created,
+    })
+}
+
+/// Random key with 256 bits of entropy, e.g. `mdr_3f9c…`
+fn generate() -> anyhow::Result<String> {
+    let mut secret = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| anyhow!("Unable to generate API key"))?;
+
+    Ok(format!("mdr_{}", hex::encode(&secret)))
+}
+
+/// Keys are random, so a plain hash is sufficient (no salt or key stretching).
+fn hash(key: &str) -> String {
+    hex::encode(digest(&SHA256, key.as_bytes()).as_ref())
+}
+
+fn not_found(id: Uuid) -> ApiError {
+    ApiError(
+        anyhow!(format!("No active API key found with id: {}", id)),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::{auth_config, token};
+    use crate::{change_set, concept};
+    use axum::body::Body;
+    use axum::http::{self, Method};
+    use axum::middleware::from_fn_with_state;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    const TREE: &str = "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7";
+
+    fn setup_router(pool: PgPool) -> Router {
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        router()
+            .merge(concept::router())
+            .merge(change_set::router())
+            .layer(from_fn_with_state(state.clone(), authenticate))
+            .with_state(state)
+    }
+
+    async fn send_request(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        authorization: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(AUTHORIZATION, authorization)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn api_key_test(pool: PgPool) {
+        let router = setup_router(pool.clone());
+        let admin = token("root", &["mdr-admin"]);
+        let reorder = json!({"parent_id": "ce3e2ac86da74b367e7d57a628022aca",
+            "children": ["a52b18659011fe8adeb112ce01327a2d", "6f12427c7db35328e268206113ac1c69"]});
+
+        // only admins manage keys
+        let (status, _) = send_request(
+            &router,
+            Method::POST,
+            "/admin/api-keys",
+            &token("alice", &["mdr-editor"]),
+            json!({"name": "etl", "scopes": ["read"]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, created) = send_request(
+            &router,
+            Method::POST,
+            "/admin/api-keys",
+            &admin,
+            json!({"name": "etl", "scopes": ["read"]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let key = format!("ApiKey {}", created["key"].as_str().unwrap());
+        let id = created["id"].as_str().unwrap();
+
+        let (status, _) = send_request(&router, Method::GET, TREE, &key, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_request(
+            &router,
+            Method::PUT,
+            &format!("{TREE}/order"),
+            &key,
+            reorder,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // listing without secrets, last use recorded
+        let (_, keys) =
+            send_request(&router, Method::GET, "/admin/api-keys", &admin, Value::Null).await;
+        assert_eq!(keys[0]["name"], "etl");
+        assert_eq!(keys[0]["scopes"], json!(["read"]));
+        assert!(keys[0]["last_used_at"].is_string());
+        assert!(keys[0].get("key").is_none() && keys[0].get("key_hash").is_none());
+        assert!(created["key"]
+            .as_str()
+            .unwrap()
+            .starts_with(keys[0]["prefix"].as_str().unwrap()));
+
+        // not updated again within a minute
+        let (status, _) = send_request(&router, Method::GET, TREE, &key, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, again) =
+            send_request(&router, Method::GET, "/admin/api-keys", &admin, Value::Null).await;
+        assert_eq!(again[0]["last_used_at"], keys[0]["last_used_at"]);
+
+        // revoked
+        let uri = format!("/admin/api-keys/{id}");
+        let (status, _) = send_request(&router, Method::DELETE, &uri, &admin, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_request(&router, Method::DELETE, &uri, &admin, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_request(&router, Method::GET, TREE, &key, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // expired
+        let (_, created) = send_request(
+            &router,
+            Method::POST,
+            "/admin/api-keys",
+            &admin,
+            json!({"name": "old", "scopes": ["admin"], "expires_at": "2020-01-01T00:00:00Z"}),
+        )
+        .await;
+        let key = format!("ApiKey {}", created["key"].as_str().unwrap());
+        let (status, _) = send_request(&router, Method::GET, TREE, &key, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn scopes_test(pool: PgPool) {
+        sqlx::query!(
+            r#"insert into module_acls (module_id, group_name, permission)
+               values ('4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'lab', 'write')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let router = setup_router(pool);
+        let admin = token("root", &["mdr-admin"]);
+        let mut keys = vec![];
+        for (name, scopes) in [
+            ("etl", json!(["write-concepts"])),
+            ("review", json!(["write-concepts"])),
+            ("ops", json!(["admin"])),
+        ] {
+            let (_, created) = send_request(
+                &router,
+                Method::POST,
+                "/admin/api-keys",
+                &admin,
+                json!({"name": name, "scopes": scopes, "groups": ["lab"]}),
+            )
+            .await;
+            keys.push(format!("ApiKey {}", created["key"].as_str().unwrap()));
+        }
+        let (etl, review, ops) = (&keys[0], &keys[1], &keys[2]);
+
+        // restricted module of the key's group
+        let (status, _) = send_request(&router, Method::GET, TREE, etl, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // writing concepts does not include administration, and vice versa
+        let (status, _) =
+            send_request(&router, Method::GET, "/admin/api-keys", etl, Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) =
+            send_request(&router, Method::GET, "/admin/api-keys", ops, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let reorder = json!({"parent_id": "ce3e2ac86da74b367e7d57a628022aca",
+            "children": ["a52b18659011fe8adeb112ce01327a2d", "6f12427c7db35328e268206113ac1c69"]});
+        let (status, _) =
+            send_request(&router, Method::PUT, &format!("{TREE}/order"), ops, reorder).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // keys author change sets, but don't review them
+        let id = Uuid::new_v4();
+        let (status, change_set) = send_request(
+            &router,
+            Method::POST,
+            "/ontology/change-sets",
+            etl,
+            json!({"id": id, "title": "Import"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(change_set["author"], "api-key:etl");
+        let concept_uri = "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d";
+        let (_, mut concept) =
+            send_request(&router, Method::GET, concept_uri, etl, Value::Null).await;
+        concept["display"] = json!("Vancomycin (Serum)");
+        let (status, _) = send_request(
+            &router,
+            Method::PUT,
+            &format!("{concept_uri}?change_set={id}"),
+            etl,
+            concept,
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let uri = format!("/ontology/change-sets/{id}");
+        let (status, _) = send_request(
+            &router,
+            Method::POST,
+            &format!("{uri}/submit"),
+            etl,
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, problem) = send_request(
+            &router,
+            Method::POST,
+            &format!("{uri}/approve"),
+            review,
+            json!({"comment": null}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            problem["detail"],
+            "Change sets cannot be reviewed with an API key"
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn rotate_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let admin = token("root", &["mdr-admin"]);
+
+        let (_, created) = send_request(
+            &router,
+            Method::POST,
+            "/admin/api-keys",
+            &admin,
+            json!({"name": "etl", "scopes": ["read", "write-concepts"], "groups": ["lab"]}),
+        )
+        .await;
+        let old = format!("ApiKey {}", created["key"].as_str().unwrap());
+        let uri = format!("/admin/api-keys/{}/rotate", created["id"].as_str().unwrap());
+
+        let (status, rotated) =
+            send_request(&router, Method::POST, &uri, &admin, Value::Null).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(rotated["scopes"], json!(["read", "write-concepts"]));
+        assert_eq!(rotated["groups"], json!(["lab"]));
+        let new = format!("ApiKey {}", rotated["key"].as_str().unwrap());
+
+        let (status, _) = send_request(&router, Method::GET, TREE, &old, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send_request(&router, Method::GET, TREE, &new, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
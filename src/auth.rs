@@ -0,0 +1,372 @@
+use crate::api_key::Scope;
+use crate::config::{AnonymousRole, Auth};
+use crate::error::ApiError;
+use crate::server::ApiContext;
+use anyhow::{anyhow, Context};
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::fs;
+use std::sync::Arc;
+
+/// Permissions of the API, each role includes the permissions of the lower ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Role {
+    /// read modules, concepts and trees
+    Reader,
+    /// draft concept edits in change sets and review them
+    Editor,
+    /// manage modules and code systems, edit published concepts directly
+    Admin,
+}
+
+/// Caller of the API
+#[derive(Debug, Clone)]
+pub(crate) struct User {
+    /// subject of the token, unknown if authentication is disabled
+    pub(crate) subject: Option<String>,
+    role: Option<Role>,
+    /// scopes of an API key, checked instead of a role
+    scopes: Option<Vec<Scope>>,
+    /// groups of the token or API key, granting access to restricted modules
+    pub(crate) groups: Vec<String>,
+}
+
+impl User {
+    #[cfg(test)]
+    pub(crate) fn new(subject: String, role: Role) -> Self {
+        User {
+            subject: Some(subject),
+            role: Some(role),
+            scopes: None,
+            groups: vec![],
+        }
+    }
+
+    pub(crate) fn api_key(name: &str, scopes: Vec<Scope>, groups: Vec<String>) -> Self {
+        User {
+            subject: Some(format!("api-key:{}", name)),
+            role: None,
+            scopes: Some(scopes),
+            groups,
+        }
+    }
+
+    pub(crate) fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    fn anonymous(role: AnonymousRole) -> Self {
+        let role = match role {
+            AnonymousRole::Reader => Role::Reader,
+            AnonymousRole::Editor => Role::Editor,
+            AnonymousRole::Admin => Role::Admin,
+        };
+        User {
+            subject: None,
+            role: Some(role),
+            scopes: None,
+            groups: vec![],
+        }
+    }
+
+    pub(crate) fn has(&self, role: Role) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|s| s.grants(role)),
+            None => self.role.is_some_and(|r| r >= role),
+        }
+    }
+
+    pub(crate) fn require(&self, role: Role) -> Result<(), ApiError> {
+        if self.has(role) {
+            return Ok(());
+        }
+        Err(ApiError(
+            anyhow!(format!("Permission denied, {:?} role required", role)),
+            StatusCode::FORBIDDEN,
+        ))
+    }
+}
+
+/// Validates bearer tokens against the keys of the identity provider
+pub(crate) struct Authenticator {
+    keys: Vec<(Option<String>, DecodingKey, Vec<Algorithm>)>,
+    config: Auth,
+}
+
+impl Authenticator {
+    /// Loads the JWKS of the identity provider, returns `None` if authentication is disabled.
+    pub(crate) fn new(config: &Auth) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let file = config.jwks_file.as_ref().ok_or(anyhow!(
+            "auth.jwks_file is required if authentication is enabled"
+        ))?;
+        let jwks: JwkSet = serde_json::from_str(
+            &fs::read_to_string(file).with_context(|| format!("Unable to read JWKS {}", file))?,
+        )?;
+
+        let mut keys = vec![];
+        for jwk in &jwks.keys {
+            // only algorithms of the key's family, prevents algorithm confusion
+            let algorithms = match jwk.algorithm {
+                AlgorithmParameters::RSA(_) => vec![
+                    Algorithm::RS256,
+                    Algorithm::RS384,
+                    Algorithm::RS512,
+                    Algorithm::PS256,
+                    Algorithm::PS384,
+                    Algorithm::PS512,
+                ],
+                AlgorithmParameters::EllipticCurve(_) => vec![Algorithm::ES256, Algorithm::ES384],
+                AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+                AlgorithmParameters::OctetKey(_) => {
+                    vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+                }
+            };
+            keys.push((
+                jwk.common.key_id.clone(),
+                DecodingKey::from_jwk(jwk)?,
+                algorithms,
+            ));
+        }
+
+        Ok(Some(Authenticator {
+            keys,
+            config: config.clone(),
+        }))
+    }
+
+    fn authenticate(&self, token: &str) -> Result<User, ApiError> {
+        let header = decode_header(token).map_err(unauthorized)?;
+        let (_, key, algorithms) = self
+            .keys
+            .iter()
+            .find(|(kid, _, _)| header.kid.is_none() || *kid == header.kid)
+            .ok_or_else(|| unauthorized("Unknown signing key"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms.clone();
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Value>(token, key, &validation)
+            .map_err(unauthorized)?
+            .claims;
+
+        Ok(User {
+            subject: claims["sub"].as_str().map(|s| s.to_owned()),
+            role: self.role(&claims),
+            scopes: None,
+            groups: claim(
+                &claims,
+                self.config.groups_claim.as_deref().unwrap_or("groups"),
+            )
+            .into_iter()
+            .map(|g| g.to_owned())
+            .collect(),
+        })
+    }
+
+    /// Highest role mapped from the roles claim
+    fn role(&self, claims: &Value) -> Option<Role> {
+        let names = claim(
+            claims,
+            self.config.roles_claim.as_deref().unwrap_or("roles"),
+        );
+
+        let mapping = &self.config.roles;
+        [
+            (Role::Admin, &mapping.admin),
+            (Role::Editor, &mapping.editor),
+            (Role::Reader, &mapping.reader),
+        ]
+        .into_iter()
+        .find(|(_, mapped)| mapped.iter().any(|m| names.contains(&m.as_str())))
+        .map(|(role, _)| role)
+    }
+}
+
+/// Values of a claim at a dotted path, either an array or a space-separated string
+fn claim<'a>(claims: &'a Value, path: &str) -> Vec<&'a str> {
+    let claim = path
+        .split('.')
+        .try_fold(claims, |value, name| value.get(name));
+    match claim {
+        Some(Value::Array(values)) => values.iter().filter_map(|v| v.as_str()).collect(),
+        Some(Value::String(values)) => values.split_whitespace().collect(),
+        _ => vec![],
+    }
+}
+
+fn unauthorized(err: impl ToString) -> ApiError {
+    ApiError(
+        anyhow!(format!("Invalid token: {}", err.to_string())),
+        StatusCode::UNAUTHORIZED,
+    )
+}
+
+impl FromRequestParts<Arc<ApiContext>> for User {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &Arc<ApiContext>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = authenticate(parts, ctx)?;
+        // caller of the access log
+        if let Some(subject) = &user.subject {
+            tracing::Span::current().record("caller", subject.as_str());
+        }
+
+        Ok(user)
+    }
+}
+
+fn authenticate(parts: &Parts, ctx: &ApiContext) -> Result<User, ApiError> {
+    // authenticated by API key
+    if let Some(user) = parts.extensions.get::<User>() {
+        return Ok(user.clone());
+    }
+    let Some(auth) = &ctx.auth else {
+        return Ok(User::anonymous(ctx.config.auth.anonymous_role));
+    };
+
+    let token = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or(ApiError(
+            anyhow!("Missing bearer token or API key"),
+            StatusCode::UNAUTHORIZED,
+        ))?;
+
+    auth.authenticate(token.trim())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const ISSUER: &str = "https://idp.example.org/realms/mdr";
+
+    /// Config with authentication using the test JWKS
+    pub(crate) fn auth_config() -> AppConfig {
+        AppConfig {
+            auth: Auth {
+                enabled: true,
+                issuer: Some(ISSUER.to_owned()),
+                audience: Some("mdr".to_owned()),
+                jwks_file: Some(
+                    concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/jwks.json").to_owned(),
+                ),
+                roles_claim: Some("realm_access.roles".to_owned()),
+                groups_claim: Some("groups".to_owned()),
+                ..Auth::default()
+            },
+            ..AppConfig::default()
+        }
+    }
+
+    /// Config without authentication, anonymous callers may edit and administrate
+    pub(crate) fn anonymous_config() -> AppConfig {
+        AppConfig {
+            auth: Auth {
+                anonymous_role: AnonymousRole::Admin,
+                ..Auth::default()
+            },
+            ..AppConfig::default()
+        }
+    }
+
+    /// Bearer token of `subject` with the given roles, signed by the test key
+    pub(crate) fn token(subject: &str, roles: &[&str]) -> String {
+        group_token(subject, roles, &[])
+    }
+
+    /// Bearer token of `subject` with the given roles and groups
+    pub(crate) fn group_token(subject: &str, roles: &[&str], groups: &[&str]) -> String {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        sign(json!({
+            "sub": subject,
+            "iss": ISSUER,
+            "aud": "mdr",
+            "exp": exp,
+            "realm_access": {"roles": roles},
+            "groups": groups
+        }))
+    }
+
+    fn sign(claims: Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("mdr-test".to_owned());
+        let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/jwt_key.pem")).unwrap();
+        format!("Bearer {}", encode(&header, &claims, &key).unwrap())
+    }
+
+    #[test]
+    fn anonymous_reads_only() {
+        let user = User::anonymous(AppConfig::default().auth.anonymous_role);
+        assert!(user.subject.is_none());
+        assert!(user.require(Role::Reader).is_ok());
+        assert!(user.require(Role::Editor).is_err());
+
+        let user = User::anonymous(anonymous_config().auth.anonymous_role);
+        assert!(user.require(Role::Admin).is_ok());
+    }
+
+    #[test]
+    fn authenticates_tokens() {
+        let auth = Authenticator::new(&auth_config().auth).unwrap().unwrap();
+        let authenticate =
+            |token: String| auth.authenticate(token.strip_prefix("Bearer ").unwrap());
+
+        let user = authenticate(group_token(
+            "alice",
+            &["offline_access", "mdr-editor"],
+            &["lab"],
+        ))
+        .unwrap();
+        assert_eq!(user.subject.as_deref(), Some("alice"));
+        assert_eq!(user.role, Some(Role::Editor));
+        assert_eq!(user.groups, vec!["lab"]);
+        assert!(user.require(Role::Reader).is_ok());
+        assert!(user.require(Role::Admin).is_err());
+
+        // no mapped role
+        let user = authenticate(token("bob", &["offline_access"])).unwrap();
+        assert!(user.require(Role::Reader).is_err());
+
+        // expired, wrong issuer
+        for claims in [
+            json!({"sub": "alice", "iss": ISSUER, "aud": "mdr", "exp": 1}),
+            json!({"sub": "alice", "iss": "https://evil.example.org", "aud": "mdr", "exp": 4102444800u64}),
+        ] {
+            let err = authenticate(sign(claims)).unwrap_err();
+            assert_eq!(err.1, StatusCode::UNAUTHORIZED);
+        }
+        // tampered
+        let err = authenticate(token("alice", &["mdr-admin"]) + "x").unwrap_err();
+        assert_eq!(err.1, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,1388 @@
+use crate::auth::{Role, User};
+use crate::concept::{self, Concept};
+use crate::error::ApiError;
+use crate::module::{self, Permission};
+use crate::server::ApiContext;
+use anyhow::anyhow;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{debug_handler, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{FromRow, PgConnection, PgExecutor};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// Concept edits which are published together after review
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, PartialEq, Clone)]
+pub(crate) struct ChangeSet {
+    id: Uuid,
+    title: String,
+    author: String,
+    status: Status,
+    reviewer: Option<String>,
+    /// review comment, e.g. the reason of a rejection
+    comment: Option<String>,
+    created_at: DateTime<Utc>,
+    reviewed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum Status {
+    /// accepts drafts
+    Open,
+    /// waiting for review
+    Submitted,
+    /// drafts published
+    Approved,
+    Rejected,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Open => "open",
+            Status::Submitted => "submitted",
+            Status::Approved => "approved",
+            Status::Rejected => "rejected",
+        }
+    }
+}
+
+/// The author is the authenticated caller
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewChangeSet {
+    id: Uuid,
+    title: String,
+}
+
+/// The reviewer is the authenticated caller
+#[derive(Deserialize, Serialize, ToSchema)]
+struct Review {
+    comment: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Create,
+    Update,
+}
+
+/// Draft of a concept compared to its published version
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+struct ConceptChange {
+    concept_id: Uuid,
+    change: Change,
+    /// names of the changed fields
+    fields: Vec<String>,
+    published: Option<Concept>,
+    draft: Concept,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new()
+        .routes(routes!(all, create))
+        .routes(routes!(read))
+        .routes(routes!(diff))
+        .routes(routes!(discard))
+        .routes(routes!(submit))
+        .routes(routes!(approve))
+        .routes(routes!(reject))
+}
+
+/// Opens a change set, the caller is its author
+#[utoipa::path(
+    post,
+    path = "/ontology/change-sets",
+    tag = "change-sets",
+    request_body = NewChangeSet,
+    responses((status = 201, description = "Created change set", body = ChangeSet))
+)]
+#[debug_handler]
+async fn create(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    change_set: Json<NewChangeSet>,
+) -> Result<(StatusCode, Json<ChangeSet>), ApiError> {
+    user.require(Role::Editor)?;
+    let author = person(&user)?;
+
+    let result = sqlx::query_as!(
+        ChangeSet,
+        r#"insert into change_sets (id, title, author) values ($1, $2, $3)
+           returning id, title, author, status as "status: Status", reviewer, comment,
+                     created_at, reviewed_at"#,
+        change_set.id,
+        change_set.title,
+        author
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(result)))
+}
+
+/// Lists the change sets, newest first
+#[utoipa::path(
+    get,
+    path = "/ontology/change-sets",
+    tag = "change-sets",
+    responses((status = 200, description = "Change sets", body = Vec<ChangeSet>))
+)]
+#[debug_handler]
+async fn all(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+) -> Result<Json<Vec<ChangeSet>>, ApiError> {
+    user.require(Role::Reader)?;
+    let hidden = hidden_modules(&ctx.db, &user).await?;
+
+    let change_sets = sqlx::query_as!(
+        ChangeSet,
+        r#"select id, title, author, status as "status: Status", reviewer, comment,
+                  created_at, reviewed_at
+           from change_sets s
+           where not exists (select from concept_drafts d
+                             where d.change_set_id = s.id and d.module_id = any($1))
+           order by created_at desc"#,
+        &hidden
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(change_sets))
+}
+
+/// Reads a change set
+#[utoipa::path(
+    get,
+    path = "/ontology/change-sets/{id}",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id")),
+    responses((status = 200, description = "Change set", body = ChangeSet))
+)]
+#[debug_handler]
+async fn read(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ChangeSet>, ApiError> {
+    user.require(Role::Reader)?;
+
+    Ok(Json(find_readable(&ctx.db, &user, id).await?))
+}
+
+/// Compares the drafts of a change set with the published concepts
+#[utoipa::path(
+    get,
+    path = "/ontology/change-sets/{id}/diff",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id")),
+    responses((status = 200, description = "Changed concepts", body = Vec<ConceptChange>))
+)]
+#[debug_handler]
+async fn diff(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<ConceptChange>>, ApiError> {
+    user.require(Role::Reader)?;
+
+    find_readable(&ctx.db, &user, id).await?;
+
+    let drafts = change_set_drafts(&ctx.db, id).await?;
+    let ids: Vec<Uuid> = drafts.iter().map(|d| d.id).collect();
+    let mut published: HashMap<Uuid, Concept> = concept::load(&ctx.db, &ids)
+        .await?
+        .into_iter()
+        .map(|c| (c.id, c))
+        .collect();
+
+    let changes = drafts
+        .into_iter()
+        .map(|draft| {
+            let published = published.remove(&draft.id);
+            ConceptChange {
+                concept_id: draft.id,
+                change: match published {
+                    Some(_) => Change::Update,
+                    None => Change::Create,
+                },
+                fields: changed_fields(published.as_ref(), &draft),
+                published,
+                draft,
+            }
+        })
+        .collect();
+
+    Ok(Json(changes))
+}
+
+/// Removes the draft of a concept from an open change set
+#[utoipa::path(
+    delete,
+    path = "/ontology/change-sets/{id}/concepts/{concept_id}",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id"), ("concept_id" = Uuid, Path, description = "Concept id")),
+    responses((status = 204, description = "Draft discarded"))
+)]
+#[debug_handler]
+async fn discard(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path((id, concept_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Role::Editor)?;
+    let author = person(&user)?;
+
+    let mut tx = ctx.db.begin().await?;
+    let change_set = lock(&mut *tx, id).await?;
+    expect_status(&change_set, Status::Open)?;
+    expect_author(&change_set, &author)?;
+
+    let result = sqlx::query!(
+        r#"delete from concept_drafts where change_set_id = $1 and concept_id = $2"#,
+        id,
+        concept_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    match result.rows_affected() {
+        0 => Err(ApiError(
+            anyhow!(format!(
+                "No draft of concept {} found in change set {}",
+                concept_id, id
+            )),
+            StatusCode::NOT_FOUND,
+        )),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+/// Submits an open change set for review
+#[utoipa::path(
+    post,
+    path = "/ontology/change-sets/{id}/submit",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id")),
+    responses((status = 200, description = "Submitted change set", body = ChangeSet))
+)]
+#[debug_handler]
+async fn submit(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ChangeSet>, ApiError> {
+    user.require(Role::Editor)?;
+    let author = person(&user)?;
+
+    let mut tx = ctx.db.begin().await?;
+    let change_set = lock(&mut *tx, id).await?;
+    expect_status(&change_set, Status::Open)?;
+    expect_author(&change_set, &author)?;
+
+    if change_set_drafts(&mut *tx, id).await?.is_empty() {
+        return Err(ApiError(
+            anyhow!("Change set does not contain any drafts"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    sqlx::query!(
+        r#"update change_sets set status = 'submitted' where id = $1"#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(find(&ctx.db, id).await?))
+}
+
+/// Publishes all drafts of the change set in a single transaction. Drafts are checked like
+/// direct edits: the reviewer needs write access to their modules and codings are validated.
+#[utoipa::path(
+    post,
+    path = "/ontology/change-sets/{id}/approve",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id")),
+    request_body = Review,
+    responses(
+        (status = 200, description = "Approved change set", body = ChangeSet),
+        (status = 409, description = "Concept changed since its draft was saved")
+    )
+)]
+#[debug_handler]
+async fn approve(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+    review: Json<Review>,
+) -> Result<Json<ChangeSet>, ApiError> {
+    user.require(Role::Editor)?;
+    let reviewer = reviewer(&user)?;
+
+    let mut tx = ctx.db.begin().await?;
+    let change_set = lock(&mut *tx, id).await?;
+    expect_status(&change_set, Status::Submitted)?;
+    expect_reviewer(&change_set, &reviewer)?;
+
+    let drafts = change_set_drafts(&mut *tx, id).await?;
+    expect_unchanged(&mut tx, id).await?;
+    for draft in publish_order(drafts) {
+        concept::require_write(&mut tx, &user, &draft).await?;
+        concept::validate_codings(&ctx, &mut tx, &draft).await?;
+        concept::save(&mut tx, &draft).await?;
+    }
+    finish(&mut *tx, id, Status::Approved, &reviewer, &review.comment).await?;
+    tx.commit().await?;
+
+    Ok(Json(find(&ctx.db, id).await?))
+}
+
+/// Rejects a submitted change set, the comment is required
+#[utoipa::path(
+    post,
+    path = "/ontology/change-sets/{id}/reject",
+    tag = "change-sets",
+    params(("id" = Uuid, Path, description = "Change set id")),
+    request_body = Review,
+    responses((status = 200, description = "Rejected change set", body = ChangeSet))
+)]
+#[debug_handler]
+async fn reject(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+    review: Json<Review>,
+) -> Result<Json<ChangeSet>, ApiError> {
+    user.require(Role::Editor)?;
+    let reviewer = reviewer(&user)?;
+
+    if review
+        .comment
+        .as_deref()
+        .is_none_or(|c| c.trim().is_empty())
+    {
+        return Err(ApiError(
+            anyhow!("A rejection requires a comment"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let mut tx = ctx.db.begin().await?;
+    let change_set = lock(&mut *tx, id).await?;
+    expect_status(&change_set, Status::Submitted)?;
+    expect_reviewer(&change_set, &reviewer)?;
+
+    finish(&mut *tx, id, Status::Rejected, &reviewer, &review.comment).await?;
+    tx.commit().await?;
+
+    Ok(Json(find(&ctx.db, id).await?))
+}
+
+/// Stores a concept edit of the author as draft of an open change set, replacing a previous
+/// draft of the concept. The published concept of the first draft is kept as its base.
+pub(crate) async fn save_draft(
+    conn: &mut PgConnection,
+    change_set_id: Uuid,
+    user: &User,
+    concept: &Concept,
+) -> Result<(), ApiError> {
+    let change_set = lock(&mut *conn, change_set_id).await?;
+    expect_status(&change_set, Status::Open)?;
+    expect_author(&change_set, &person(user)?)?;
+    let base = concept::load(&mut *conn, &[concept.id]).await?.pop();
+
+    sqlx::query!(
+        r#"insert into concept_drafts (change_set_id, concept_id, module_id, concept, base)
+           values ($1, $2, $3, $4, $5)
+           on conflict (change_set_id, concept_id) do update
+           set (module_id, concept, updated_at) = (excluded.module_id, excluded.concept, now())"#,
+        change_set_id,
+        concept.id,
+        concept.module_id,
+        sqlx::types::Json(concept) as _,
+        sqlx::types::Json(base) as _
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Concept as edited in a change set: its draft, or the published concept
+pub(crate) async fn current(
+    conn: &mut PgConnection,
+    change_set_id: Uuid,
+    concept_id: Uuid,
+) -> Result<Option<Concept>, sqlx::Error> {
+    let draft = sqlx::query_scalar!(
+        r#"select concept as "concept: sqlx::types::Json<Concept>"
+           from concept_drafts where change_set_id = $1 and concept_id = $2"#,
+        change_set_id,
+        concept_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match draft {
+        Some(draft) => Ok(Some(draft.0)),
+        None => Ok(concept::load(&mut *conn, &[concept_id]).await?.pop()),
+    }
+}
+
+/// Latest drafts of change sets under edit or review, of a module or by concept id.
+/// Ordered like the concepts of a tree.
+pub(crate) async fn drafts(
+    db: impl PgExecutor<'_>,
+    module_id: Option<Uuid>,
+    ids: &[Uuid],
+) -> Result<Vec<Concept>, sqlx::Error> {
+    let drafts = sqlx::query_scalar!(
+        r#"select l.concept as "concept!: sqlx::types::Json<Concept>"
+           from (select distinct on (d.concept_id) d.concept
+                 from concept_drafts d join change_sets s on s.id = d.change_set_id
+                 where s.status in ('open', 'submitted')
+                 and (d.module_id = $1 or d.concept_id = any($2))
+                 order by d.concept_id, d.updated_at desc) l
+           order by (l.concept ->> 'sort_order')::integer nulls last, (l.concept ->> 'leaf')::boolean,
+                    l.concept ->> 'display' collate "de-x-icu""#,
+        module_id,
+        ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(drafts.into_iter().map(|d| d.0).collect())
+}
+
+async fn change_set_drafts(db: impl PgExecutor<'_>, id: Uuid) -> Result<Vec<Concept>, sqlx::Error> {
+    let drafts = sqlx::query_scalar!(
+        r#"select concept as "concept: sqlx::types::Json<Concept>"
+           from concept_drafts where change_set_id = $1 order by updated_at"#,
+        id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(drafts.into_iter().map(|d| d.0).collect())
+}
+
+/// Checks that the published concepts did not change since their drafts were saved,
+/// approving would overwrite these changes. Locks the concepts until publishing.
+async fn expect_unchanged(conn: &mut PgConnection, id: Uuid) -> Result<(), ApiError> {
+    let bases = sqlx::query!(
+        r#"select concept_id, base as "base!: sqlx::types::Json<Option<Concept>>"
+           from concept_drafts where change_set_id = $1 and base is not null"#,
+        id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let ids: Vec<Uuid> = bases.iter().map(|b| b.concept_id).collect();
+    sqlx::query_scalar!(
+        r#"select id from concepts where id = any($1) order by id for update"#,
+        &ids
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut published: HashMap<Uuid, Concept> = concept::load(&mut *conn, &ids)
+        .await?
+        .into_iter()
+        .map(|c| (c.id, c))
+        .collect();
+    for base in bases {
+        let current = published.remove(&base.concept_id);
+        if serde_json::to_value(&current)? != serde_json::to_value(&base.base.0)? {
+            return Err(ApiError(
+                anyhow!(format!(
+                    "Concept {} has been changed since its draft was saved",
+                    base.concept_id
+                )),
+                StatusCode::CONFLICT,
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn find(db: impl PgExecutor<'_>, id: Uuid) -> Result<ChangeSet, ApiError> {
+    sqlx::query_as!(
+        ChangeSet,
+        r#"select id, title, author, status as "status: Status", reviewer, comment,
+                  created_at, reviewed_at
+           from change_sets where id = $1"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| not_found(id))
+}
+
+/// Finds a change set, unless it contains drafts of modules the user cannot read.
+async fn find_readable(
+    db: impl PgExecutor<'_> + Copy,
+    user: &User,
+    id: Uuid,
+) -> Result<ChangeSet, ApiError> {
+    let hidden = hidden_modules(db, user).await?;
+    sqlx::query_as!(
+        ChangeSet,
+        r#"select id, title, author, status as "status: Status", reviewer, comment,
+                  created_at, reviewed_at
+           from change_sets s
+           where id = $1
+           and not exists (select from concept_drafts d
+                           where d.change_set_id = s.id and d.module_id = any($2))"#,
+        id,
+        &hidden
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| not_found(id))
+}
+
+async fn hidden_modules(db: impl PgExecutor<'_>, user: &User) -> Result<Vec<Uuid>, sqlx::Error> {
+    Ok(module::restricted(db, user, Permission::Read)
+        .await?
+        .into_iter()
+        .collect())
+}
+
+/// Locks the change set for status changes and edits.
+async fn lock(db: impl PgExecutor<'_>, id: Uuid) -> Result<ChangeSet, ApiError> {
+    sqlx::query_as!(
+        ChangeSet,
+        r#"select id, title, author, status as "status: Status", reviewer, comment,
+                  created_at, reviewed_at
+           from change_sets where id = $1 for update"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| not_found(id))
+}
+
+async fn finish(
+    db: impl PgExecutor<'_>,
+    id: Uuid,
+    status: Status,
+    reviewer: &str,
+    comment: &Option<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update change_sets set (status, reviewer, comment, reviewed_at) = ($2, $3, $4, now())
+           where id = $1"#,
+        id,
+        status as Status,
+        reviewer,
+        comment.as_deref()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+fn expect_status(change_set: &ChangeSet, status: Status) -> Result<(), ApiError> {
+    if change_set.status != status {
+        return Err(ApiError(
+            anyhow!(format!(
+                "Change set {} is {}",
+                change_set.id,
+                change_set.status.as_str()
+            )),
+            StatusCode::CONFLICT,
+        ));
+    }
+    Ok(())
+}
+
+/// Only the author edits and submits a change set, a reviewer could otherwise approve
+/// their own drafts.
+fn expect_author(change_set: &ChangeSet, author: &str) -> Result<(), ApiError> {
+    if change_set.author != author {
+        return Err(ApiError(
+            anyhow!(format!(
+                "Change set {} can only be changed by its author",
+                change_set.id
+            )),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    Ok(())
+}
+
+fn expect_reviewer(change_set: &ChangeSet, reviewer: &str) -> Result<(), ApiError> {
+    if change_set.author == reviewer {
+        return Err(ApiError(
+            anyhow!("Change sets must be reviewed by another person than their author"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    Ok(())
+}
+
+/// Subject of the token or API key. Anonymous callers cannot take part in change sets, the
+/// four-eyes principle requires known authors and reviewers.
+fn person(user: &User) -> Result<String, ApiError> {
+    user.subject.clone().ok_or_else(|| {
+        ApiError(
+            anyhow!("Change sets require an authenticated user"),
+            StatusCode::FORBIDDEN,
+        )
+    })
+}
+
+/// Reviewers are persons, API keys of machine clients only author change sets.
+fn reviewer(user: &User) -> Result<String, ApiError> {
+    if user.is_api_key() {
+        return Err(ApiError(
+            anyhow!("Change sets cannot be reviewed with an API key"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    person(user)
+}
+
+fn not_found(id: Uuid) -> ApiError {
+    ApiError(
+        anyhow!(format!("No change set found with id: {}", id)),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+/// Orders drafts so new parents are published before their children.
+fn publish_order(mut drafts: Vec<Concept>) -> Vec<Concept> {
+    let mut ordered = Vec::with_capacity(drafts.len());
+    while !drafts.is_empty() {
+        let pending: HashSet<Uuid> = drafts.iter().map(|d| d.id).collect();
+        let (ready, rest): (Vec<Concept>, Vec<Concept>) = drafts
+            .into_iter()
+            .partition(|d| d.parent_id.is_none_or(|p| !pending.contains(&p)));
+        if ready.is_empty() {
+            // parent cycle within the change set, rejected when saving
+            ordered.extend(rest);
+            break;
+        }
+        ordered.extend(ready);
+        drafts = rest;
+    }
+    ordered
+}
+
+fn changed_fields(published: Option<&Concept>, draft: &Concept) -> Vec<String> {
+    let draft = serde_json::to_value(draft).unwrap_or_default();
+    let published = published
+        .map(|p| serde_json::to_value(p).unwrap_or_default())
+        .unwrap_or_default();
+
+    match draft.as_object() {
+        Some(fields) => fields
+            .iter()
+            .filter(|(name, value)| published.get(name.as_str()) != Some(value))
+            .map(|(name, _)| name.clone())
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::{anonymous_config, auth_config, group_token, token};
+    use crate::config::{AppConfig, Validation, ValidationMode};
+    use axum::body::Body;
+    use axum::http::{self, Method, Request};
+    use axum::response::Response;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    const VANCOMYCIN: &str = "a52b1865-9011-fe8a-deb1-12ce01327a2d";
+
+    fn setup_router(pool: PgPool) -> Router {
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        router().merge(concept::router()).with_state(state)
+    }
+
+    /// Sends a request as editor
+    async fn send_request(
+        router: &Router,
+        user: &str,
+        method: Method,
+        uri: &str,
+        body: Value,
+    ) -> Response {
+        router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(http::header::AUTHORIZATION, token(user, &["mdr-editor"]))
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn parse_json(response: Response) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Creates a change set with a draft renaming Vancomycin
+    async fn setup_change_set(router: &Router) -> String {
+        let id = Uuid::new_v4();
+        let response = send_request(
+            router,
+            "alice",
+            Method::POST,
+            "/ontology/change-sets",
+            json!({"id": id, "title": "Rename Vancomycin"}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let concept_uri = format!("/ontology/concepts/{VANCOMYCIN}");
+        let mut concept =
+            parse_json(send_request(router, "alice", Method::GET, &concept_uri, json!(null)).await)
+                .await;
+        concept["display"] = json!("Vancomycin (Serum)");
+        let response = send_request(
+            router,
+            "alice",
+            Method::PUT,
+            &format!("{concept_uri}?change_set={id}"),
+            concept,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        format!("/ontology/change-sets/{id}")
+    }
+
+    async fn display(router: &Router, query: &str) -> Value {
+        let response = send_request(
+            router,
+            "alice",
+            Method::GET,
+            &format!("/ontology/concepts/{VANCOMYCIN}{query}"),
+            json!(null),
+        )
+        .await;
+        parse_json(response).await["display"].clone()
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn approve_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let uri = setup_change_set(&router).await;
+
+        // drafts are only served on request
+        assert_eq!(display(&router, "").await, json!("Vancomycin"));
+        assert_eq!(
+            display(&router, "?include=drafts").await,
+            json!("Vancomycin (Serum)")
+        );
+
+        let response = send_request(
+            &router,
+            "alice",
+            Method::GET,
+            &format!("{uri}/diff"),
+            json!(null),
+        )
+        .await;
+        let diff = parse_json(response).await;
+        assert_eq!(diff[0]["change"], json!("update"));
+        assert_eq!(diff[0]["fields"], json!(["display"]));
+        assert_eq!(diff[0]["published"]["display"], json!("Vancomycin"));
+
+        let review = json!({"comment": null});
+        // not submitted yet
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/approve"),
+            review.clone(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/submit"),
+            json!(null),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // four-eyes principle
+        let response = send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/approve"),
+            review.clone(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/approve"),
+            review,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let change_set = parse_json(response).await;
+        assert_eq!(change_set["status"], json!("approved"));
+        assert_eq!(change_set["reviewer"], json!("bob"));
+
+        assert_eq!(display(&router, "").await, json!("Vancomycin (Serum)"));
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn editor_drafts_test(pool: PgPool) {
+        let router = setup_router(pool);
+        const ANTIBIOTIKA: &str = "ce3e2ac8-6da7-4b36-7e7d-57a628022aca";
+        const VORICONAZOL: &str = "6f12427c-7db3-5328-e268-206113ac1c69";
+
+        // published concepts are only changed by admins
+        let concept_uri = format!("/ontology/concepts/{VANCOMYCIN}");
+        let concept = parse_json(
+            send_request(&router, "alice", Method::GET, &concept_uri, json!(null)).await,
+        )
+        .await;
+        let response = send_request(&router, "alice", Method::PUT, &concept_uri, concept).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{concept_uri}/move"),
+            json!({"parent_id": VORICONAZOL}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut uris = vec![];
+        for title in ["Move", "Order"] {
+            let id = Uuid::new_v4();
+            let response = send_request(
+                &router,
+                "alice",
+                Method::POST,
+                "/ontology/change-sets",
+                json!({"id": id, "title": title}),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            uris.push((id, format!("/ontology/change-sets/{id}")));
+        }
+
+        // moves and sibling orders are drafts like edits
+        let response = send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{concept_uri}/move?change_set={}", uris[0].0),
+            json!({"parent_id": VORICONAZOL}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(parse_json(response).await["parent_id"], json!(VORICONAZOL));
+        let response = send_request(
+            &router,
+            "alice",
+            Method::PUT,
+            &format!(
+                "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/order?change_set={}",
+                uris[1].0
+            ),
+            json!({"parent_id": ANTIBIOTIKA, "children": [VANCOMYCIN, VORICONAZOL]}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let published = parse_json(
+            send_request(&router, "alice", Method::GET, &concept_uri, json!(null)).await,
+        )
+        .await;
+        assert_eq!(published["parent_id"], json!(ANTIBIOTIKA));
+        assert_eq!(published["sort_order"], json!(null));
+
+        for ((_, uri), expected) in uris.iter().zip([
+            vec![(VANCOMYCIN, vec!["parent_id"]), (VORICONAZOL, vec!["leaf"])],
+            vec![
+                (VANCOMYCIN, vec!["sort_order"]),
+                (VORICONAZOL, vec!["sort_order"]),
+            ],
+        ]) {
+            let diff = parse_json(
+                send_request(
+                    &router,
+                    "alice",
+                    Method::GET,
+                    &format!("{uri}/diff"),
+                    json!(null),
+                )
+                .await,
+            )
+            .await;
+            let mut changes: Vec<(String, Vec<String>)> = diff
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| {
+                    let fields = serde_json::from_value(c["fields"].clone()).unwrap();
+                    (c["concept_id"].as_str().unwrap().to_owned(), fields)
+                })
+                .collect();
+            changes.sort();
+            let mut expected: Vec<(String, Vec<String>)> = expected
+                .into_iter()
+                .map(|(id, fields)| {
+                    let fields = fields.into_iter().map(|f| f.to_owned()).collect();
+                    (id.to_owned(), fields)
+                })
+                .collect();
+            expected.sort();
+            assert_eq!(changes, expected, "{uri}");
+        }
+
+        // published on approval
+        let (_, uri) = &uris[0];
+        send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/submit"),
+            json!(null),
+        )
+        .await;
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/approve"),
+            json!({"comment": null}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let published = parse_json(
+            send_request(&router, "alice", Method::GET, &concept_uri, json!(null)).await,
+        )
+        .await;
+        assert_eq!(published["parent_id"], json!(VORICONAZOL));
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn author_only_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let uri = setup_change_set(&router).await;
+        let id = uri.rsplit('/').next().unwrap();
+
+        // the reviewer cannot inject drafts, discard or submit
+        let concept_uri = format!("/ontology/concepts/{VANCOMYCIN}");
+        let mut concept =
+            parse_json(send_request(&router, "bob", Method::GET, &concept_uri, json!(null)).await)
+                .await;
+        concept["display"] = json!("Vancomycin (injected)");
+        for (method, uri, body) in [
+            (
+                Method::PUT,
+                format!("{concept_uri}?change_set={id}"),
+                concept,
+            ),
+            (
+                Method::DELETE,
+                format!("{uri}/concepts/{VANCOMYCIN}"),
+                json!(null),
+            ),
+            (Method::POST, format!("{uri}/submit"), json!(null)),
+        ] {
+            let response = send_request(&router, "bob", method, &uri, body).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+            assert_eq!(
+                parse_json(response).await["detail"],
+                json!(format!("Change set {id} can only be changed by its author"))
+            );
+        }
+
+        let response = send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/submit"),
+            json!(null),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/approve"),
+            json!({"comment": null}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(display(&router, "").await, json!("Vancomycin (Serum)"));
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn approve_conflict_test(pool: PgPool) {
+        let router = setup_router(pool.clone());
+        let uri = setup_change_set(&router).await;
+
+        // published concept changed after the draft was saved, e.g. by an admin
+        sqlx::query!(
+            r#"update concepts set selectable = false where id = $1"#,
+            Uuid::parse_str(VANCOMYCIN).unwrap()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/submit"),
+            json!(null),
+        )
+        .await;
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/approve"),
+            json!({"comment": null}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            parse_json(response).await["detail"],
+            json!(format!(
+                "Concept {VANCOMYCIN} has been changed since its draft was saved"
+            ))
+        );
+        assert_eq!(display(&router, "").await, json!("Vancomycin"));
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn tree_drafts_test(pool: PgPool) {
+        let parent_id = "ce3e2ac8-6da7-4b36-7e7d-57a628022aca";
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"insert into change_sets (id, title, author) values ($1, 'Metals', 'alice')"#,
+            id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let alice = User::new("alice".to_owned(), Role::Editor);
+        let vancomycin = Uuid::parse_str(VANCOMYCIN).unwrap();
+        let template = json!(concept::load(&pool, &[vancomycin]).await.unwrap()[0]);
+        for (display, sort_order, leaf) in [
+            ("Chrom", None, true),
+            ("Äpfel", None, true),
+            ("Blei", None, false),
+            ("Zink", Some(1), true),
+        ] {
+            let mut concept = template.clone();
+            concept["id"] = json!(Uuid::new_v4());
+            concept["display"] = json!(display);
+            concept["sort_order"] = json!(sort_order);
+            concept["leaf"] = json!(leaf);
+            concept["term_codes"] = json!([]);
+            let concept: Concept = serde_json::from_value(concept).unwrap();
+            save_draft(&mut pool.acquire().await.unwrap(), id, &alice, &concept)
+                .await
+                .unwrap();
+        }
+
+        let router = setup_router(pool);
+        let response = send_request(
+            &router,
+            "alice",
+            Method::GET,
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7?include=drafts",
+            json!(null),
+        )
+        .await;
+        let tree = parse_json(response).await;
+        let parent = tree[0]["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["id"] == parent_id)
+            .unwrap();
+        let displays: Vec<&str> = parent["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["display"].as_str().unwrap())
+            .collect();
+
+        // new drafts after the published concepts, sorted like them
+        assert_eq!(
+            displays[displays.len() - 4..],
+            ["Zink", "Blei", "Äpfel", "Chrom"]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn anonymous_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool, anonymous_config()).unwrap());
+        let router = router().with_state(state);
+
+        // authors and reviewers must be known for the four-eyes principle
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/ontology/change-sets")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        json!({"id": Uuid::new_v4(), "title": "Anonymous"}).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            parse_json(response).await["detail"],
+            json!("Change sets require an authenticated user")
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn reject_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let uri = setup_change_set(&router).await;
+        send_request(
+            &router,
+            "alice",
+            Method::POST,
+            &format!("{uri}/submit"),
+            json!(null),
+        )
+        .await;
+
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/reject"),
+            json!({"comment": null}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = send_request(
+            &router,
+            "bob",
+            Method::POST,
+            &format!("{uri}/reject"),
+            json!({"comment": "Keep the lab's naming"}),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let change_set = parse_json(response).await;
+        assert_eq!(change_set["status"], json!("rejected"));
+        assert_eq!(change_set["comment"], json!("Keep the lab's naming"));
+
+        // rejected drafts are neither published nor served
+        assert_eq!(display(&router, "").await, json!("Vancomycin"));
+        assert_eq!(
+            display(&router, "?include=drafts").await,
+            json!("Vancomycin")
+        );
+
+        let response = send_request(
+            &router,
+            "alice",
+            Method::DELETE,
+            &format!("{uri}/concepts/{VANCOMYCIN}"),
+            json!(null),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn restricted_test(pool: PgPool) {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"insert into module_acls (module_id, group_name, permission)
+               values ('4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'lab', 'write')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"insert into change_sets (id, title, author) values ($1, 'Lab codes', 'alice')"#,
+            id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let alice = User::new("alice".to_owned(), Role::Editor);
+        let vancomycin = Uuid::parse_str(VANCOMYCIN).unwrap();
+        let concept = concept::load(&pool, &[vancomycin]).await.unwrap().remove(0);
+        save_draft(&mut pool.acquire().await.unwrap(), id, &alice, &concept)
+            .await
+            .unwrap();
+
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        let router = router().with_state(state);
+        let get = |uri: String, groups: &'static [&'static str]| {
+            router.clone().oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(
+                        http::header::AUTHORIZATION,
+                        group_token("bob", &["mdr-reader"], groups),
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        for (groups, found) in [(&[] as &[&str], false), (&["lab"], true)] {
+            let response = get("/ontology/change-sets".to_owned(), groups)
+                .await
+                .unwrap();
+            let change_sets = parse_json(response).await;
+            assert_eq!(change_sets.as_array().unwrap().len(), found as usize);
+
+            for uri in [
+                format!("/ontology/change-sets/{id}"),
+                format!("/ontology/change-sets/{id}/diff"),
+            ] {
+                let status = get(uri.clone(), groups).await.unwrap().status();
+                let expected = match found {
+                    true => StatusCode::OK,
+                    false => StatusCode::NOT_FOUND,
+                };
+                assert_eq!(status, expected, "{}", uri);
+            }
+        }
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn approve_checks_drafts_test(pool: PgPool) {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"insert into module_acls (module_id, group_name, permission)
+               values ('4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'lab', 'write'),
+                      ('4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'research', 'read')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"insert into change_sets (id, title, author) values ($1, 'Typo', 'alice')"#,
+            id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let alice = User::new("alice".to_owned(), Role::Editor);
+        // saved while validation was lenient
+        let vancomycin = Uuid::parse_str(VANCOMYCIN).unwrap();
+        let mut concept = json!(concept::load(&pool, &[vancomycin]).await.unwrap()[0]);
+        concept["term_codes"][0]["system"] = json!("http://loinc.or");
+        let concept: Concept = serde_json::from_value(concept).unwrap();
+        save_draft(&mut pool.acquire().await.unwrap(), id, &alice, &concept)
+            .await
+            .unwrap();
+        sqlx::query!(
+            r#"update change_sets set status = 'submitted' where id = $1"#,
+            id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let config = AppConfig {
+            validation: Validation {
+                mode: ValidationMode::Strict,
+            },
+            ..auth_config()
+        };
+        let state = Arc::new(ApiContext::new(pool, config).unwrap());
+        let router = router().with_state(state);
+        let approve = |groups: &'static [&'static str]| {
+            router.clone().oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(format!("/ontology/change-sets/{id}/approve"))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(
+                        http::header::AUTHORIZATION,
+                        group_token("bob", &["mdr-editor"], groups),
+                    )
+                    .body(Body::from(json!({"comment": null}).to_string()))
+                    .unwrap(),
+            )
+        };
+
+        // read only
+        let response = approve(&["research"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = approve(&["lab"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            parse_json(response).await["detail"],
+            json!("Invalid codings: Unknown code system: http://loinc.or")
+        );
+    }
+
+    #[test]
+    fn publishes_parents_first() {
+        let concept = |parent_id: Option<Uuid>| {
+            let mut c = Concept::default();
+            c.id = Uuid::new_v4();
+            c.parent_id = parent_id;
+            c
+        };
+        let parent = concept(None);
+        let child = concept(Some(parent.id));
+        // parent not part of the change set
+        let other = concept(Some(Uuid::new_v4()));
+
+        let ordered: Vec<Uuid> = publish_order(vec![child.clone(), other.clone(), parent.clone()])
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ordered, vec![other.id, parent.id, child.id]);
+    }
+}
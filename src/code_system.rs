@@ -0,0 +1,385 @@
+use crate::auth::{Role, User};
+use crate::coding::Coding;
+use crate::error::ApiError;
+use crate::server::ApiContext;
+use anyhow::anyhow;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{debug_handler, extract::State, Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{FromRow, PgExecutor};
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, PartialEq, Clone)]
+pub(crate) struct CodeSystem {
+    id: Uuid,
+    /// canonical url, used as `Coding.system`
+    url: String,
+    name: String,
+    /// known versions, any version is accepted if empty
+    versions: Vec<String>,
+    publisher: Option<String>,
+    /// local (site specific) or external code system
+    local: bool,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new()
+        .routes(routes!(all, create))
+        .routes(routes!(read, update, delete))
+}
+
+/// Registers a code system
+#[utoipa::path(
+    post,
+    path = "/ontology/code-systems",
+    tag = "code-systems",
+    request_body = CodeSystem,
+    responses((status = 201, description = "Created code system", body = CodeSystem))
+)]
+#[debug_handler]
+async fn create(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    code_system: Json<CodeSystem>,
+) -> Result<(StatusCode, Json<CodeSystem>), ApiError> {
+    user.require(Role::Admin)?;
+
+    let result = sqlx::query_as!(
+        CodeSystem,
+        r#"insert into code_systems (id,url,name,versions,publisher,local)
+           values ($1,$2,$3,$4,$5,$6)
+           RETURNING id,url,name,versions,publisher,local"#,
+        code_system.id,
+        code_system.url,
+        code_system.name,
+        &code_system.versions,
+        code_system.publisher,
+        code_system.local
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(result)))
+}
+
+/// Lists the registered code systems by name
+#[utoipa::path(
+    get,
+    path = "/ontology/code-systems",
+    tag = "code-systems",
+    responses((status = 200, description = "Code systems", body = Vec<CodeSystem>))
+)]
+#[debug_handler]
+async fn all(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+) -> Result<Json<Vec<CodeSystem>>, ApiError> {
+    user.require(Role::Reader)?;
+
+    let code_systems = sqlx::query_as!(
+        CodeSystem,
+        r#"select id, url, name, versions, publisher, local
+           from code_systems order by name"#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(code_systems))
+}
+
+/// Reads a code system
+#[utoipa::path(
+    get,
+    path = "/ontology/code-systems/{id}",
+    tag = "code-systems",
+    params(("id" = Uuid, Path, description = "Code system id")),
+    responses((status = 200, description = "Code system", body = CodeSystem))
+)]
+#[debug_handler]
+async fn read(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<CodeSystem>, ApiError> {
+    user.require(Role::Reader)?;
+
+    let result = sqlx::query_as!(
+        CodeSystem,
+        r#"select id, url, name, versions, publisher, local
+           from code_systems where id = $1"#,
+        id
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    result.map(Json).ok_or_else(|| not_found(id))
+}
+
+/// Updates a code system
+#[utoipa::path(
+    put,
+    path = "/ontology/code-systems/{id}",
+    tag = "code-systems",
+    params(("id" = Uuid, Path, description = "Code system id")),
+    request_body = CodeSystem,
+    responses(
+        (status = 200, description = "Updated code system", body = CodeSystem),
+        (status = 400, description = "Id of the code system differs from the path"),
+    )
+)]
+#[debug_handler]
+async fn update(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+    code_system: Json<CodeSystem>,
+) -> Result<Json<CodeSystem>, ApiError> {
+    user.require(Role::Admin)?;
+    if code_system.id != id {
+        return Err(ApiError(
+            anyhow!(format!(
+                "Code system id {} differs from path id {}",
+                code_system.id, id
+            )),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let result = sqlx::query_as!(
+        CodeSystem,
+        r#"update code_systems set (url,name,versions,publisher,local) = ($2,$3,$4,$5,$6)
+           where id = $1
+           RETURNING id,url,name,versions,publisher,local"#,
+        id,
+        code_system.url,
+        code_system.name,
+        &code_system.versions,
+        code_system.publisher,
+        code_system.local
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    result.map(Json).ok_or_else(|| not_found(id))
+}
+
+/// Deletes a code system
+#[utoipa::path(
+    delete,
+    path = "/ontology/code-systems/{id}",
+    tag = "code-systems",
+    params(("id" = Uuid, Path, description = "Code system id")),
+    responses((status = 204, description = "Code system deleted"))
+)]
+#[debug_handler]
+async fn delete(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Role::Admin)?;
+
+    let result = sqlx::query!(r#"delete from code_systems where id = $1"#, id)
+        .execute(&ctx.db)
+        .await?;
+
+    match result.rows_affected() {
+        0 => Err(not_found(id)),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+fn not_found(id: Uuid) -> ApiError {
+    ApiError(
+        anyhow!(format!("No code system found with id: {}", id)),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+/// Checks system and version of the codings against the registry and returns the problems found.
+pub(crate) async fn validate(
+    db: impl PgExecutor<'_>,
+    codings: &[&Coding],
+) -> Result<Vec<String>, sqlx::Error> {
+    let systems: Vec<String> = codings.iter().map(|c| c.system.clone()).collect();
+    let registered: HashMap<String, Vec<String>> = sqlx::query!(
+        r#"select url, versions from code_systems where url = any($1)"#,
+        &systems
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| (r.url, r.versions))
+    .collect();
+
+    let mut problems = vec![];
+    for c in codings {
+        match (registered.get(&c.system), &c.version) {
+            (None, _) => problems.push(format!("Unknown code system: {}", c.system)),
+            (Some(versions), Some(version))
+                if !versions.is_empty() && !versions.contains(version) =>
+            {
+                problems.push(format!(
+                    "Unknown version {} of code system {}",
+                    version, c.system
+                ))
+            }
+            _ => {}
+        }
+    }
+    problems.dedup();
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::anonymous_config;
+    use axum::body::Body;
+    use axum::http::{self, Request, StatusCode};
+    use http_body_util::BodyExt;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    #[sqlx::test]
+    async fn crud_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool, anonymous_config()).unwrap());
+        let router = router().with_state(state);
+
+        let mut code_system = CodeSystem {
+            id: Uuid::new_v4(),
+            url: "http://example.org/CodeSystem/test".to_owned(),
+            name: "Test".to_owned(),
+            versions: vec!["1.0.0".to_owned()],
+            publisher: None,
+            local: true,
+        };
+        let uri = format!("/ontology/code-systems/{}", code_system.id);
+
+        // create
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/ontology/code-systems")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&code_system).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // update
+        code_system.versions.push("1.1.0".to_owned());
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri(&uri)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&code_system).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // update with another id
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri(format!("/ontology/code-systems/{}", Uuid::new_v4()))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&code_system).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // read
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            serde_json::from_slice::<CodeSystem>(&body).unwrap(),
+            code_system
+        );
+
+        // delete
+        for status in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::DELETE)
+                        .uri(&uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[sqlx::test]
+    async fn validate_test(pool: PgPool) {
+        let coding = |system: &str, version: Option<&str>| Coding {
+            code: "x".to_owned(),
+            system: system.to_owned(),
+            display: "x".to_owned(),
+            version: version.map(|v| v.to_owned()),
+        };
+        sqlx::query!(
+            r#"update code_systems set versions = '{2.73,2.74}' where url = 'http://loinc.org'"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let codings = [
+            coding("http://loinc.org", Some("2.73")),
+            coding("http://loinc.org", Some("2.42")),
+            coding("http://fhir.de/CodeSystem/bfarm/icd-10-gm", Some("2024")),
+            coding("http://loinc.or", None),
+        ];
+        let problems = validate(&pool, &codings.iter().collect::<Vec<_>>())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            problems,
+            vec![
+                "Unknown version 2.42 of code system http://loinc.org",
+                "Unknown code system: http://loinc.or",
+            ]
+        );
+    }
+}
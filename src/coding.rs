@@ -0,0 +1,87 @@
+use serde_derive::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Clone, Debug)]
+pub(crate) struct Coding {
+    pub(crate) code: String,
+    pub(crate) system: String,
+    pub(crate) display: String,
+    pub(crate) version: Option<String>,
+}
+
+/// Role of a coding linked to a concept
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CodingKind {
+    TermCode,
+    FilterOption,
+}
+
+impl CodingKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            CodingKind::TermCode => "term_code",
+            CodingKind::FilterOption => "filter_option",
+        }
+    }
+}
+
+/// Replaces the codings of a concept of the given kind, keeping their order.
+///
+/// Codings are shared between concepts by (system, code, version), their display is kept
+/// per concept.
+pub(crate) async fn save_codings(
+    conn: &mut PgConnection,
+    concept_id: Uuid,
+    kind: CodingKind,
+    codings: &[Coding],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"delete from concept_codings where concept_id = $1 and kind = $2"#,
+        concept_id,
+        kind.as_str()
+    )
+    .execute(&mut *conn)
+    .await?;
+    if codings.is_empty() {
+        return Ok(());
+    }
+
+    let systems: Vec<String> = codings.iter().map(|c| c.system.clone()).collect();
+    let codes: Vec<String> = codings.iter().map(|c| c.code.clone()).collect();
+    let versions: Vec<Option<String>> = codings.iter().map(|c| c.version.clone()).collect();
+    let displays: Vec<String> = codings.iter().map(|c| c.display.clone()).collect();
+
+    sqlx::query!(
+        r#"insert into codings (system, code, version, display)
+           select distinct on (system, code, version) system, code, version, display
+           from unnest($1::text[], $2::text[], $3::text[], $4::text[]) c(system, code, version, display)
+           on conflict on constraint codings_system_code_version_key do nothing"#,
+        &systems,
+        &codes,
+        &versions as &[Option<String>],
+        &displays
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"insert into concept_codings (concept_id, coding_id, kind, position, display)
+           select $1, k.id, $2, l.position, l.display
+           from unnest($3::text[], $4::text[], $5::text[], $6::text[])
+                with ordinality l(system, code, version, display, position)
+           join codings k on k.system = l.system and k.code = l.code
+                          and k.version is not distinct from l.version"#,
+        concept_id,
+        kind.as_str(),
+        &systems,
+        &codes,
+        &versions as &[Option<String>],
+        &displays
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
@@ -1,52 +1,94 @@
+use crate::auth::{Role, User};
+use crate::coding::{save_codings, Coding, CodingKind};
+use crate::config::ValidationMode;
+use crate::designation::{self, languages, save_designations, Designation, Lang};
 use crate::error::ApiError;
+use crate::etag;
+use crate::module::{self, Module, Permission};
 use crate::server::ApiContext;
+use crate::tree_cache::TreeKey;
+use crate::{change_set, code_system, terminology};
 use anyhow::anyhow;
-use axum::extract::{Path, State};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_TYPE, IF_MATCH};
+use axum::http::HeaderMap;
 pub use axum::http::StatusCode;
-use axum::routing::{get, post};
+use axum::response::Response;
 use axum::{debug_handler, Router};
 use serde_derive::{Deserialize, Serialize};
 use sqlx::types::{Json, Uuid};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgConnection, PgExecutor, PgPool};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-
-#[derive(Deserialize, Serialize, FromRow, Clone, Debug)]
-struct Coding {
-    code: String,
-    system: String,
-    display: String,
-    version: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize, FromRow, Clone, Default)]
-struct Concept {
-    id: Uuid,
+use std::time::Instant;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+#[derive(Debug, Deserialize, Serialize, FromRow, ToSchema, Clone, Default)]
+pub(crate) struct Concept {
+    pub(crate) id: Uuid,
     display: String,
-    parent_id: Option<Uuid>,
-    module_id: Uuid,
+    pub(crate) parent_id: Option<Uuid>,
+    pub(crate) module_id: Uuid,
+    #[schema(value_type = Option<Vec<Coding>>)]
     term_codes: Option<Json<Vec<Coding>>>,
     leaf: bool,
     time_restriction_allowed: Option<bool>,
     filter_type: Option<String>,
     selectable: bool,
+    #[schema(value_type = Option<Vec<Coding>>)]
     filter_options: Option<Json<Vec<Coding>>>,
     version: String,
+    sort_order: Option<i32>,
+    #[schema(value_type = Option<Vec<Designation>>)]
+    designations: Option<Json<Vec<Designation>>>,
+    /// markdown
+    description: Option<String>,
+    definition: Option<String>,
+    inclusion_note: Option<String>,
+    exclusion_note: Option<String>,
+    #[schema(value_type = Option<Vec<Link>>)]
+    links: Option<Json<Vec<Link>>>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Concept with its nested children
+#[derive(Deserialize, Serialize, ToSchema, Clone, Debug)]
 struct ConceptTree {
     id: Uuid,
     display: String,
     parent_id: Option<Uuid>,
     module_id: Uuid,
+    #[schema(value_type = Option<Vec<Coding>>)]
     term_codes: Option<Json<Vec<Coding>>>,
     leaf: bool,
     time_restriction_allowed: Option<bool>,
     filter_type: Option<String>,
     selectable: bool,
+    #[schema(value_type = Option<Vec<Coding>>)]
     filter_options: Option<Json<Vec<Coding>>>,
     version: String,
+    sort_order: Option<i32>,
+    #[schema(value_type = Option<Vec<Designation>>)]
+    designations: Option<Json<Vec<Designation>>>,
+    // metadata, only included on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    definition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inclusion_note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exclusion_note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Link>>)]
+    links: Option<Json<Vec<Link>>>,
+    /// parent is missing from the module or part of a parent cycle, the concept is listed as root
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    orphaned: bool,
     #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[schema(no_recursion)]
     children: Vec<ConceptTree>,
 }
 
@@ -64,97 +106,282 @@ impl From<Concept> for ConceptTree {
             selectable: c.selectable,
             filter_options: c.filter_options,
             version: c.version,
+            sort_order: c.sort_order,
+            designations: c.designations,
+            description: c.description,
+            definition: c.definition,
+            inclusion_note: c.inclusion_note,
+            exclusion_note: c.exclusion_note,
+            links: c.links,
+            orphaned: false,
             children: vec![],
         }
     }
 }
 
-impl PartialEq for ConceptTree {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+/// Link to external documentation
+#[derive(Deserialize, Serialize, ToSchema, Clone, Debug)]
+struct Link {
+    title: String,
+    url: String,
+}
+
+/// Optional parts of bulk responses, e.g. `?include=metadata`
+#[derive(Deserialize, Serialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+struct Include {
+    /// `metadata` for concept metadata, `drafts` (tree only) for pending change sets
+    include: Option<String>,
+}
+
+impl Include {
+    fn has(&self, part: &str) -> bool {
+        self.include
+            .as_deref()
+            .is_some_and(|i| i.split(',').any(|p| p.trim() == part))
     }
 }
-impl From<&Concept> for ConceptTree {
-    fn from(c: &Concept) -> Self {
-        c.clone().into()
+
+const METADATA: &str = "metadata";
+const DRAFTS: &str = "drafts";
+
+#[derive(Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Draft {
+    /// saves the edit as draft of the change set, required unless the caller is an admin
+    change_set: Option<Uuid>,
+}
+
+/// Editors save concept edits as drafts of a change set, which is published after review.
+/// Only admins change published concepts directly.
+fn require_draft(user: &User, draft: &Draft) -> Result<(), ApiError> {
+    if draft.change_set.is_some() || user.has(Role::Admin) {
+        return Ok(());
     }
+    Err(ApiError(
+        anyhow!("Concept edits require a change set, only admins edit published concepts"),
+        StatusCode::FORBIDDEN,
+    ))
 }
 
-impl ConceptTree {
-    pub(crate) fn add_child(&mut self, child: &ConceptTree) {
-        self.children.push(child.clone());
+impl Concept {
+    fn strip_metadata(&mut self) {
+        self.description = None;
+        self.definition = None;
+        self.inclusion_note = None;
+        self.exclusion_note = None;
+        self.links = None;
     }
 
-    pub(crate) fn add_child_to_tree(&mut self, child: &ConceptTree) -> bool {
-        if self.id == child.parent_id.unwrap() {
-            self.add_child(child);
-            return true;
+    /// Uses the preferred designation of the first matching language as display.
+    fn localize(&mut self, languages: &[String]) {
+        let Some(designations) = &self.designations else {
+            return;
+        };
+        if let Some(d) = designation::preferred(designations, languages) {
+            self.display = d.value.clone();
         }
+    }
+}
 
-        for c in self.children.iter_mut() {
-            c.add_child_to_tree(child);
-        }
-        false
+impl PartialEq for ConceptTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+impl From<&Concept> for ConceptTree {
+    fn from(c: &Concept) -> Self {
+        c.clone().into()
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum SearchResult {
     Tree,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 struct Search {
     module_id: Uuid,
     search_term: String,
     display: Option<SearchResult>,
 }
 
+#[derive(Deserialize, Serialize, ToSchema, IntoParams, Clone, Debug)]
+#[into_params(parameter_in = Query)]
+struct CodingRef {
+    system: String,
+    code: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Debug)]
+struct Ancestor {
+    id: Uuid,
+    display: String,
+}
+
+/// Concept using a coding, with its module and ancestors (root first)
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+struct CodingMatch {
+    concept: Concept,
+    module: Module,
+    ancestors: Vec<Ancestor>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+struct CodingLookup {
+    system: String,
+    code: String,
+    concepts: Vec<CodingMatch>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct Move {
+    parent_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct SiblingOrder {
+    parent_id: Option<Uuid>,
+    children: Vec<Uuid>,
+}
+
 pub(crate) fn router() -> Router<Arc<ApiContext>> {
-    Router::new()
-        .route("/ontology/tree/{module_id}", get(ontology))
-        .route("/ontology/concepts/search", post(search))
-        .route(
-            "/ontology/concepts/{concept_id}",
-            get(read).put(create_or_update),
-        )
+    routes().into()
 }
 
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new()
+        .routes(routes!(ontology))
+        .routes(routes!(reorder))
+        .routes(routes!(lookup))
+        .routes(routes!(lookup_batch))
+        .routes(routes!(search))
+        .routes(routes!(read, create_or_update))
+        .routes(routes!(move_concept))
+}
+
+/// Reads the concept tree of a module
+#[utoipa::path(
+    get,
+    path = "/ontology/tree/{module_id}",
+    tag = "concepts",
+    params(("module_id" = Uuid, Path, description = "Module id"), Lang, Include),
+    responses(
+        (status = 200, description = "Root concepts with their children", body = Vec<ConceptTree>),
+        (status = 304, description = "Not modified since the `If-None-Match` entity tag")
+    )
+)]
 #[debug_handler]
 async fn ontology(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
     Path(module_id): Path<Uuid>,
-) -> Result<axum::Json<Vec<ConceptTree>>, ApiError> {
-    let result = sqlx::query_as!(
+    Query(lang): Query<Lang>,
+    Query(include): Query<Include>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    user.require(Role::Reader)?;
+    module::require_access(&ctx.db, &user, module_id, Permission::Read).await?;
+
+    let languages = languages(&lang, &headers);
+    let key = TreeKey {
+        module_id,
+        languages: languages.join(","),
+        include: include.include.clone().unwrap_or_default(),
+    };
+    let cached = ctx.trees.get(&key);
+    ctx.metrics.observe_tree_cache(cached.is_some());
+    if let Some(tree) = cached {
+        if etag::not_modified(&headers, &tree.etag) {
+            return Ok(etag::tagged(StatusCode::NOT_MODIFIED, &tree.etag));
+        }
+        let json = [(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())];
+        return Ok(etag::tagged((json, tree.json.clone()), &tree.etag));
+    }
+    let generation = ctx.trees.generation(module_id);
+
+    // the revision is bumped by each change of the module's concepts and drafts
+    let revision = sqlx::query_scalar!(r#"select revision from modules where id = $1"#, module_id)
+        .fetch_optional(&ctx.db)
+        .await?
+        .unwrap_or_default();
+    let etag = etag::entity_tag(&[
+        module_id.as_bytes(),
+        &revision.to_be_bytes(),
+        key.languages.as_bytes(),
+        key.include.as_bytes(),
+    ]);
+    if etag::not_modified(&headers, &etag) {
+        return Ok(etag::tagged(StatusCode::NOT_MODIFIED, &etag));
+    }
+
+    let mut result = sqlx::query_as!(
         Concept,
-        r#"with recursive ontology as (
-               (select * from concepts where module_id = $1 and parent_id is null
-                order by leaf,display)
-                union all select c.* from concepts c
-                join ontology on c.parent_id = ontology.id
-           )
-           select id as "id!", display as "display!",parent_id,module_id as "module_id!",
-                term_codes as "term_codes: Json<Vec<Coding>>",leaf as "leaf!",
-                time_restriction_allowed,filter_type,selectable as "selectable!",
-                filter_options as "filter_options: Json<Vec<Coding>>", version as "version!"
-                from ontology"#,
-        module_id
+        r#"select id as "id!", display as "display!", parent_id, module_id as "module_id!",
+                  term_codes as "term_codes: Json<Vec<Coding>>", leaf as "leaf!",
+                  time_restriction_allowed, filter_type, selectable as "selectable!",
+                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!", sort_order,
+                  designations as "designations: Json<Vec<Designation>>",
+                  case when $2 then description end as description,
+                  case when $2 then definition end as definition,
+                  case when $2 then inclusion_note end as inclusion_note,
+                  case when $2 then exclusion_note end as exclusion_note,
+                  case when $2 then links end as "links: Json<Vec<Link>>"
+           from concepts_with_codings where module_id = $1
+           order by sort_order nulls last, leaf, display collate "de-x-icu""#,
+        module_id,
+        include.has(METADATA)
     )
     .fetch_all(&ctx.db)
     .await?;
 
+    if include.has(DRAFTS) {
+        let ids: Vec<Uuid> = result.iter().map(|c| c.id).collect();
+        let mut drafts = change_set::drafts(&ctx.db, Some(module_id), &ids).await?;
+        if !include.has(METADATA) {
+            drafts.iter_mut().for_each(Concept::strip_metadata);
+        }
+        result = with_drafts(result, drafts, module_id);
+    }
+    localize(&mut result, &languages);
     // build tree
+    let started = Instant::now();
     let tree = build_concept_tree(result);
-
-    Ok(axum::Json(tree))
+    ctx.metrics.observe_tree_build(started.elapsed());
+
+    let json = Bytes::from(serde_json::to_vec(&tree)?);
+    ctx.trees
+        .insert(key, generation, etag.clone(), json.clone());
+    Ok(etag::tagged(
+        ([(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())], json),
+        &etag,
+    ))
 }
 
+/// Searches the selectable concepts of a module by display, designation or term code
+#[utoipa::path(
+    post,
+    path = "/ontology/concepts/search",
+    tag = "concepts",
+    params(Lang, Include),
+    request_body = Search,
+    responses((status = 200, description = "Matching concepts, nested if `display` is `tree`", body = Vec<ConceptTree>))
+)]
 #[debug_handler]
 async fn search(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Query(lang): Query<Lang>,
+    Query(include): Query<Include>,
+    headers: HeaderMap,
     search: axum::Json<Search>,
 ) -> Result<axum::Json<Vec<ConceptTree>>, ApiError> {
+    user.require(Role::Reader)?;
+    module::require_access(&ctx.db, &user, search.module_id, Permission::Read).await?;
+
     if search.search_term.len() < 2 {
         return Err(ApiError(
             anyhow!("Search term must consist of at least 2 characters"),
@@ -163,25 +390,39 @@ async fn search(
     }
 
     let term_like = format!("%{}%", search.search_term.to_lowercase());
-    let result: Vec<Concept> = sqlx::query_as!(
+    let mut result: Vec<Concept> = sqlx::query_as!(
         Concept,
-        r#"select id as "id!", display as "display!",parent_id,module_id as "module_id!",
-                  term_codes as "term_codes: Json<Vec<Coding>>",leaf as "leaf!",
-                  time_restriction_allowed,filter_type,selectable as "selectable!",
-                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!"
-           from concepts
+        r#"select id as "id!", display as "display!", parent_id, module_id as "module_id!",
+                  term_codes as "term_codes: Json<Vec<Coding>>", leaf as "leaf!",
+                  time_restriction_allowed, filter_type, selectable as "selectable!",
+                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!", sort_order,
+                  designations as "designations: Json<Vec<Designation>>",
+                  case when $4 then description end as description,
+                  case when $4 then definition end as definition,
+                  case when $4 then inclusion_note end as inclusion_note,
+                  case when $4 then exclusion_note end as exclusion_note,
+                  case when $4 then links end as "links: Json<Vec<Link>>"
+           from concepts_with_codings
            where module_id = $1
            and selectable is true
            and (lower(display) like lower($2)
-           or exists(select 1 from jsonb_array_elements(term_codes) o(obj) where lower(o.obj ->> 'code') like $3)
-           )"#,
+           or exists(select 1 from concept_codings cc join codings k on k.id = cc.coding_id
+                     where cc.concept_id = concepts_with_codings.id and cc.kind = 'term_code'
+                     and lower(k.code) like $3)
+           or exists(select 1 from concept_designations d
+                     where d.concept_id = concepts_with_codings.id and lower(d.value) like $2)
+           )
+           order by sort_order nulls last, leaf, display collate "de-x-icu""#,
         search.module_id,
         term_like,
         term_like,
+        include.has(METADATA),
     )
         .fetch_all(&ctx.db)
         .await?;
 
+    ctx.metrics.observe_search(result.len());
+    localize(&mut result, &languages(&lang, &headers));
     let tree: Vec<ConceptTree> = if search.display.is_some() {
         to_tree(result)
     } else {
@@ -190,25 +431,213 @@ async fn search(
     Ok(axum::Json(tree))
 }
 
+/// Finds the concepts using a coding as term code
+#[utoipa::path(
+    get,
+    path = "/ontology/concepts",
+    tag = "concepts",
+    params(CodingRef),
+    responses((status = 200, description = "Concepts with their module and ancestors", body = Vec<CodingMatch>))
+)]
+#[debug_handler]
+async fn lookup(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Query(coding): Query<CodingRef>,
+) -> Result<axum::Json<Vec<CodingMatch>>, ApiError> {
+    user.require(Role::Reader)?;
+
+    let hidden = module::restricted(&ctx.db, &user, Permission::Read).await?;
+    let result = find_by_codings(&ctx.db, vec![coding], &hidden).await?;
+
+    Ok(axum::Json(
+        result.into_iter().flat_map(|l| l.concepts).collect(),
+    ))
+}
+
+/// Finds the concepts using each of the codings as term code
+#[utoipa::path(
+    post,
+    path = "/ontology/concepts/lookup",
+    tag = "concepts",
+    request_body = Vec<CodingRef>,
+    responses((status = 200, description = "Concepts of each coding, in request order", body = Vec<CodingLookup>))
+)]
+#[debug_handler]
+async fn lookup_batch(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    axum::Json(codings): axum::Json<Vec<CodingRef>>,
+) -> Result<axum::Json<Vec<CodingLookup>>, ApiError> {
+    user.require(Role::Reader)?;
+
+    if codings.len() > MAX_LOOKUP_CODINGS {
+        return Err(ApiError(
+            anyhow!(format!(
+                "Lookup is limited to {} codings per request",
+                MAX_LOOKUP_CODINGS
+            )),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let hidden = module::restricted(&ctx.db, &user, Permission::Read).await?;
+    Ok(axum::Json(
+        find_by_codings(&ctx.db, codings, &hidden).await?,
+    ))
+}
+
+const MAX_LOOKUP_CODINGS: usize = 10_000;
+
+/// Finds the concepts with each of the given codings in their term codes.
+/// Results are in the order of `codings`, concepts of `hidden` modules are excluded.
+async fn find_by_codings(
+    db: &PgPool,
+    codings: Vec<CodingRef>,
+    hidden: &HashSet<Uuid>,
+) -> Result<Vec<CodingLookup>, sqlx::Error> {
+    let (systems, codes): (Vec<String>, Vec<String>) = codings
+        .iter()
+        .map(|c| (c.system.clone(), c.code.clone()))
+        .unzip();
+
+    let matches = sqlx::query!(
+        r#"select distinct l.position as "position!", c.id, c.display
+           from unnest($1::text[], $2::text[]) with ordinality l(system, code, position)
+           join codings k on k.system = l.system and k.code = l.code
+           join concept_codings cc on cc.coding_id = k.id and cc.kind = 'term_code'
+           join concepts c on c.id = cc.concept_id
+           order by l.position, c.display"#,
+        &systems,
+        &codes
+    )
+    .fetch_all(db)
+    .await?;
+    let ids: Vec<Uuid> = matches.iter().map(|m| m.id).collect();
+
+    let concepts: HashMap<Uuid, Concept> = load(db, &ids)
+        .await?
+        .into_iter()
+        .filter(|c| !hidden.contains(&c.module_id))
+        .map(|c| (c.id, c))
+        .collect();
+
+    let mut ancestors: HashMap<Uuid, Vec<Ancestor>> = HashMap::new();
+    // depth limit guards against parent cycles
+    for a in sqlx::query!(
+        r#"with recursive ancestors as (
+               select c.id as concept_id, p.id, p.display, p.parent_id, 1 as depth
+               from concepts c join concepts p on p.id = c.parent_id
+               where c.id = any($1)
+               union all
+               select a.concept_id, p.id, p.display, p.parent_id, a.depth + 1
+               from ancestors a join concepts p on p.id = a.parent_id
+               where a.depth < 64
+           )
+           select concept_id as "concept_id!", id as "id!", display as "display!"
+           from ancestors order by depth desc"#,
+        &ids
+    )
+    .fetch_all(db)
+    .await?
+    {
+        ancestors.entry(a.concept_id).or_default().push(Ancestor {
+            id: a.id,
+            display: a.display,
+        });
+    }
+
+    let module_ids: Vec<Uuid> = concepts.values().map(|c| c.module_id).collect();
+    let modules: HashMap<Uuid, Module> = sqlx::query_as!(
+        Module,
+        r#"select id, name, fdpg_cds_code, fdpg_cds_system, fdpg_cds_version, version
+           from modules where id = any($1)"#,
+        &module_ids
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|m| (m.id, m))
+    .collect();
+
+    let mut result: Vec<CodingLookup> = codings
+        .into_iter()
+        .map(|c| CodingLookup {
+            system: c.system,
+            code: c.code,
+            concepts: vec![],
+        })
+        .collect();
+    for m in matches {
+        let (Some(concept), Some(lookup)) =
+            (concepts.get(&m.id), result.get_mut(m.position as usize - 1))
+        else {
+            continue;
+        };
+        lookup.concepts.push(CodingMatch {
+            concept: concept.clone(),
+            module: modules[&concept.module_id].clone(),
+            ancestors: ancestors.get(&m.id).cloned().unwrap_or_default(),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Loads the published concepts with the given ids.
+pub(crate) async fn load(
+    db: impl PgExecutor<'_>,
+    ids: &[Uuid],
+) -> Result<Vec<Concept>, sqlx::Error> {
+    sqlx::query_as!(
+        Concept,
+        r#"select id as "id!", display as "display!", parent_id, module_id as "module_id!",
+                  term_codes as "term_codes: Json<Vec<Coding>>", leaf as "leaf!",
+                  time_restriction_allowed, filter_type, selectable as "selectable!",
+                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!", sort_order,
+                  designations as "designations: Json<Vec<Designation>>",
+                  description, definition, inclusion_note, exclusion_note,
+                  links as "links: Json<Vec<Link>>"
+           from concepts_with_codings where id = any($1)"#,
+        ids
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Reads a concept including its metadata
+#[utoipa::path(
+    get,
+    path = "/ontology/concepts/{concept_id}",
+    tag = "concepts",
+    params(("concept_id" = Uuid, Path, description = "Concept id"), Lang, Include),
+    responses(
+        (status = 200, description = "Concept", body = Concept),
+        (status = 304, description = "Not modified since the `If-None-Match` entity tag")
+    )
+)]
 #[debug_handler]
 async fn read(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
     Path(id): Path<Uuid>,
-) -> Result<axum::Json<Concept>, ApiError> {
-    let result = sqlx::query_as!(
-        Concept,
-        r#"select id as "id!", display as "display!",parent_id,module_id as "module_id!",
-                  term_codes as "term_codes: Json<Vec<Coding>>",leaf as "leaf!",
-                  time_restriction_allowed,filter_type,selectable as "selectable!",
-                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!"
-           from concepts where id = $1"#,
-        id
+    Query(lang): Query<Lang>,
+    Query(include): Query<Include>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    user.require(Role::Reader)?;
+
+    let result = representation(
+        &mut *ctx.db.acquire().await?,
+        id,
+        include.has(DRAFTS),
+        &languages(&lang, &headers),
     )
-    .fetch_optional(&ctx.db)
     .await?;
+    let hidden = module::restricted(&ctx.db, &user, Permission::Read).await?;
 
-    match result {
-        Some(concept) => Ok(axum::Json(concept)),
+    match result.filter(|c| !hidden.contains(&c.module_id)) {
+        Some(concept) => etag::cached_json(&headers, &concept),
         None => Err(ApiError(
             anyhow!(format!("No concept found with id: {}", id)),
             StatusCode::NOT_FOUND,
@@ -216,237 +645,1484 @@ async fn read(
     }
 }
 
+/// Creates or updates a concept, as draft of a change set unless the caller is an admin
+#[utoipa::path(
+    put,
+    path = "/ontology/concepts/{concept_id}",
+    tag = "concepts",
+    params(("concept_id" = Uuid, Path, description = "Concept id"), Draft, Lang, Include),
+    request_body = Concept,
+    params(("If-Match" = Option<String>, Header, description = "Entity tag of the concept as read with the same `lang` and `include`, drafts are included for drafts")),
+    responses(
+        (status = 200, description = "Concept updated"),
+        (status = 201, description = "Concept created"),
+        (status = 202, description = "Draft saved to the change set"),
+        (status = 412, description = "Concept modified since the `If-Match` entity tag")
+    )
+)]
 #[debug_handler]
 async fn create_or_update(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Query(draft): Query<Draft>,
+    Query(lang): Query<Lang>,
+    Query(include): Query<Include>,
+    headers: HeaderMap,
     concept: axum::Json<Concept>,
 ) -> Result<(StatusCode, ()), ApiError> {
-    let inserted:Option<bool> = sqlx::query_scalar!(
-        r#"insert into concepts (id,display,parent_id,module_id, term_codes,leaf,
-                  time_restriction_allowed,filter_type,selectable,filter_options,version)
-           values ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
-           on conflict(id) do update set (id,display,parent_id,module_id, term_codes,leaf,
-                  time_restriction_allowed,filter_type,selectable,filter_options,version)
-               = (excluded.id,excluded.display,excluded.parent_id,excluded.module_id, excluded.term_codes,excluded.leaf,
-                  excluded.time_restriction_allowed,excluded.filter_type,excluded.selectable,excluded.filter_options,
-                  excluded.version)
+    user.require(Role::Editor)?;
+    require_draft(&user, &draft)?;
+
+    let mut tx = ctx.db.begin().await?;
+    require_write(&mut tx, &user, &concept).await?;
+    validate_codings(&ctx, &mut tx, &concept).await?;
+    // optimistic concurrency, the concept must not have changed since the editor read it
+    if headers.contains_key(IF_MATCH) {
+        sqlx::query_scalar!(
+            r#"select id from concepts where id = $1 for update"#,
+            concept.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let current = representation(
+            &mut tx,
+            concept.id,
+            include.has(DRAFTS) || draft.change_set.is_some(),
+            &languages(&lang, &headers),
+        )
+        .await?;
+        let etag = match current {
+            Some(current) => Some(etag::content_tag(&serde_json::to_vec(&current)?)),
+            None => None,
+        };
+        etag::require_match(&headers, etag.as_deref())?;
+    }
+
+    // edit of a change set, published on approval
+    if let Some(change_set_id) = draft.change_set {
+        change_set::save_draft(&mut tx, change_set_id, &user, &concept).await?;
+        tx.commit().await?;
+        return Ok((StatusCode::ACCEPTED, ()));
+    }
+
+    let inserted = save(&mut tx, &concept).await?;
+    tx.commit().await?;
+
+    let status = if inserted {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status, ()))
+}
+
+/// Checks the user's write access to the module of a concept. Moving a concept to another
+/// module requires access to both.
+pub(crate) async fn require_write(
+    conn: &mut PgConnection,
+    user: &User,
+    concept: &Concept,
+) -> Result<(), ApiError> {
+    module::require_access(&mut *conn, user, concept.module_id, Permission::Write).await?;
+    let current_module = sqlx::query_scalar!(
+        r#"select module_id from concepts where id = $1"#,
+        concept.id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(module_id) = current_module.filter(|m| *m != concept.module_id) {
+        module::require_access(&mut *conn, user, module_id, Permission::Write).await?;
+    }
+    Ok(())
+}
+
+/// Concept as read, or its latest draft if requested
+async fn current(
+    conn: &mut PgConnection,
+    id: Uuid,
+    with_drafts: bool,
+) -> Result<Option<Concept>, sqlx::Error> {
+    if with_drafts && let Some(draft) = change_set::drafts(&mut *conn, None, &[id]).await?.pop() {
+        return Ok(Some(draft));
+    }
+    Ok(load(&mut *conn, &[id]).await?.pop())
+}
+
+/// Concept as served by `read`, in the display languages
+async fn representation(
+    conn: &mut PgConnection,
+    id: Uuid,
+    with_drafts: bool,
+    languages: &[String],
+) -> Result<Option<Concept>, sqlx::Error> {
+    let mut concept = current(conn, id, with_drafts).await?;
+    if let Some(concept) = concept.as_mut() {
+        concept.localize(languages);
+    }
+    Ok(concept)
+}
+
+/// Creates or updates a concept with its codings and designations.
+/// Returns whether the concept has been created.
+pub(crate) async fn save(conn: &mut PgConnection, concept: &Concept) -> Result<bool, ApiError> {
+    if let Some(parent_id) = concept.parent_id
+        && creates_cycle(&mut *conn, concept.id, parent_id).await?
+    {
+        return Err(cycle_error(concept.id, parent_id));
+    }
+
+    let inserted: Option<bool> = sqlx::query_scalar!(
+        r#"insert into concepts (id,display,parent_id,module_id,leaf,
+                  time_restriction_allowed,filter_type,selectable,version,sort_order,
+                  description,definition,inclusion_note,exclusion_note,links)
+           values ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15)
+           on conflict(id) do update set (id,display,parent_id,module_id,leaf,
+                  time_restriction_allowed,filter_type,selectable,version,sort_order,
+                  description,definition,inclusion_note,exclusion_note,links)
+               = (excluded.id,excluded.display,excluded.parent_id,excluded.module_id,excluded.leaf,
+                  excluded.time_restriction_allowed,excluded.filter_type,excluded.selectable,
+                  excluded.version,excluded.sort_order,excluded.description,excluded.definition,
+                  excluded.inclusion_note,excluded.exclusion_note,excluded.links)
         RETURNING (xmax = 0) AS inserted"#,
         concept.id,
         concept.display,
         concept.parent_id,
         concept.module_id,
-        concept.term_codes.clone().map(Json) as _,
         concept.leaf,
         concept.time_restriction_allowed,
         concept.filter_type,
         concept.selectable,
-        concept.filter_options.clone().map(Json) as _,
-        concept.version
+        concept.version,
+        concept.sort_order,
+        concept.description,
+        concept.definition,
+        concept.inclusion_note,
+        concept.exclusion_note,
+        &concept.links as &Option<Json<Vec<Link>>>
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut *conn)
     .await?;
 
-    let status = if inserted.ok_or(anyhow!("Unable to determine update or create operation"))? {
-        StatusCode::CREATED
-    } else {
-        StatusCode::OK
+    for (kind, codings) in [
+        (CodingKind::TermCode, &concept.term_codes),
+        (CodingKind::FilterOption, &concept.filter_options),
+    ] {
+        let codings = codings.as_ref().map(|c| c.as_slice()).unwrap_or_default();
+        save_codings(&mut *conn, concept.id, kind, codings).await?;
+    }
+    let designations = concept.designations.as_ref().map(|d| d.as_slice());
+    save_designations(&mut *conn, concept.id, designations.unwrap_or_default()).await?;
+
+    Ok(inserted.ok_or(anyhow!("Unable to determine update or create operation"))?)
+}
+
+/// Moves a concept with its descendants below another parent, as draft of a change set
+/// unless the caller is an admin
+#[utoipa::path(
+    post,
+    path = "/ontology/concepts/{concept_id}/move",
+    tag = "concepts",
+    params(("concept_id" = Uuid, Path, description = "Concept id"), Draft),
+    request_body = Move,
+    responses(
+        (status = 200, description = "Moved concept", body = Concept),
+        (status = 202, description = "Move saved to the change set", body = Concept)
+    )
+)]
+#[debug_handler]
+async fn move_concept(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+    Query(draft): Query<Draft>,
+    target: axum::Json<Move>,
+) -> Result<(StatusCode, axum::Json<Concept>), ApiError> {
+    user.require(Role::Editor)?;
+    require_draft(&user, &draft)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    let current = sqlx::query!(
+        r#"select module_id, parent_id from concepts where id = $1 for update"#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| {
+        ApiError(
+            anyhow!(format!("No concept found with id: {}", id)),
+            StatusCode::NOT_FOUND,
+        )
+    })?;
+    module::require_access(&ctx.db, &user, current.module_id, Permission::Write).await?;
+
+    if let Some(parent_id) = target.parent_id {
+        let parent_module = sqlx::query_scalar!(
+            r#"select module_id from concepts where id = $1 for update"#,
+            parent_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match parent_module {
+            None => {
+                return Err(ApiError(
+                    anyhow!(format!("No parent concept found with id: {}", parent_id)),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            Some(module_id) if module_id != current.module_id => {
+                return Err(ApiError(
+                    anyhow!("Concepts can only be moved within their module"),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            _ => {}
+        }
+        if creates_cycle(&mut *tx, id, parent_id).await? {
+            return Err(cycle_error(id, parent_id));
+        }
+    }
+
+    if let Some(change_set_id) = draft.change_set {
+        let moved = move_draft(
+            &mut tx,
+            change_set_id,
+            &user,
+            id,
+            current.parent_id,
+            target.parent_id,
+        )
+        .await?;
+        tx.commit().await?;
+        return Ok((StatusCode::ACCEPTED, axum::Json(moved)));
+    }
+
+    // the previous sort order refers to the old siblings
+    sqlx::query!(
+        r#"update concepts set parent_id = $2, sort_order = null where id = $1"#,
+        id,
+        target.parent_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if let Some(old_parent) = current.parent_id {
+        sqlx::query!(
+            r#"update concepts set leaf = not exists(select 1 from concepts where parent_id = $1)
+               where id = $1"#,
+            old_parent
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    if let Some(new_parent) = target.parent_id {
+        sqlx::query!(
+            r#"update concepts set leaf = false where id = $1"#,
+            new_parent
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let moved = sqlx::query_as!(
+        Concept,
+        r#"select id as "id!", display as "display!", parent_id, module_id as "module_id!",
+                  term_codes as "term_codes: Json<Vec<Coding>>", leaf as "leaf!",
+                  time_restriction_allowed, filter_type, selectable as "selectable!",
+                  filter_options as "filter_options: Json<Vec<Coding>>", version as "version!", sort_order,
+                  designations as "designations: Json<Vec<Designation>>",
+                  description, definition, inclusion_note, exclusion_note,
+                  links as "links: Json<Vec<Link>>"
+           from concepts_with_codings where id = $1"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, axum::Json(moved)))
+}
+
+/// Saves a move as drafts of the change set: the moved concept and the leaf flags of its
+/// old and new parent
+async fn move_draft(
+    conn: &mut PgConnection,
+    change_set_id: Uuid,
+    user: &User,
+    id: Uuid,
+    old_parent: Option<Uuid>,
+    new_parent: Option<Uuid>,
+) -> Result<Concept, ApiError> {
+    let not_found = |id: Uuid| {
+        ApiError(
+            anyhow!(format!("No concept found with id: {}", id)),
+            StatusCode::NOT_FOUND,
+        )
     };
 
-    Ok((status, ()))
+    let mut moved = change_set::current(&mut *conn, change_set_id, id)
+        .await?
+        .ok_or_else(|| not_found(id))?;
+    // the previous sort order refers to the old siblings
+    moved.parent_id = new_parent;
+    moved.sort_order = None;
+    change_set::save_draft(&mut *conn, change_set_id, user, &moved).await?;
+
+    if let Some(old_parent) = old_parent.filter(|p| Some(*p) != new_parent) {
+        let siblings = sqlx::query_scalar!(
+            r#"select exists(select 1 from concepts where parent_id = $1 and id <> $2)
+                  as "exists!""#,
+            old_parent,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let mut parent = change_set::current(&mut *conn, change_set_id, old_parent)
+            .await?
+            .ok_or_else(|| not_found(old_parent))?;
+        if !siblings && !parent.leaf {
+            parent.leaf = true;
+            change_set::save_draft(&mut *conn, change_set_id, user, &parent).await?;
+        }
+    }
+    if let Some(new_parent) = new_parent {
+        let mut parent = change_set::current(&mut *conn, change_set_id, new_parent)
+            .await?
+            .ok_or_else(|| not_found(new_parent))?;
+        if parent.leaf {
+            parent.leaf = false;
+            change_set::save_draft(&mut *conn, change_set_id, user, &parent).await?;
+        }
+    }
+
+    Ok(moved)
 }
 
-fn build_concept_tree(concepts: Vec<Concept>) -> Vec<ConceptTree> {
-    let mut tree: Vec<ConceptTree> = vec![];
-    for c in concepts {
-        match c.parent_id {
-            Some(_) => {
-                tree.iter_mut()
-                    .any(|t| t.add_child_to_tree(&c.clone().into()));
+/// Validates the concept's codings against the code system registry. Depending on the
+/// validation mode, problems are rejected or logged.
+pub(crate) async fn validate_codings(
+    ctx: &ApiContext,
+    conn: &mut PgConnection,
+    concept: &Concept,
+) -> Result<(), ApiError> {
+    let codings: Vec<&Coding> = [&concept.term_codes, &concept.filter_options]
+        .into_iter()
+        .flatten()
+        .flat_map(|c| c.iter())
+        .collect();
+
+    let mut problems = code_system::validate(&mut *conn, &codings).await?;
+    problems.extend(terminology::validate(&mut *conn, &codings).await?);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    match ctx.config.validation.mode {
+        ValidationMode::Strict => Err(ApiError(
+            anyhow!(format!("Invalid codings: {}", problems.join("; "))),
+            StatusCode::BAD_REQUEST,
+        )),
+        ValidationMode::Lenient => {
+            for p in problems {
+                tracing::warn!("Concept {}: {}", concept.id, p);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Checks whether `parent_id` is `id` itself or one of its descendants.
+async fn creates_cycle(
+    db: impl PgExecutor<'_>,
+    id: Uuid,
+    parent_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let cycle = sqlx::query_scalar!(
+        r#"with recursive ancestors as (
+               select id, parent_id from concepts where id = $2
+               union
+               select c.id, c.parent_id from concepts c
+               join ancestors a on c.id = a.parent_id
+           )
+           select exists(select 1 from ancestors where id = $1) or $1 = $2 as "cycle!""#,
+        id,
+        parent_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(cycle)
+}
+
+fn cycle_error(id: Uuid, parent_id: Uuid) -> ApiError {
+    ApiError(
+        anyhow!(format!(
+            "Concept {} cannot be placed below {}: this would create a cycle",
+            id, parent_id
+        )),
+        StatusCode::BAD_REQUEST,
+    )
+}
+
+/// Sets the order of the children of a parent, or of the roots, as drafts of a change set
+/// unless the caller is an admin
+#[utoipa::path(
+    put,
+    path = "/ontology/tree/{module_id}/order",
+    tag = "concepts",
+    params(("module_id" = Uuid, Path, description = "Module id"), Draft),
+    request_body = SiblingOrder,
+    responses(
+        (status = 202, description = "Order saved to the change set"),
+        (status = 204, description = "Order saved")
+    )
+)]
+#[debug_handler]
+async fn reorder(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(module_id): Path<Uuid>,
+    Query(draft): Query<Draft>,
+    order: axum::Json<SiblingOrder>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Role::Editor)?;
+    require_draft(&user, &draft)?;
+    module::require_access(&ctx.db, &user, module_id, Permission::Write).await?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    let siblings: HashSet<Uuid> = sqlx::query_scalar!(
+        r#"select id from concepts
+           where module_id = $1 and parent_id is not distinct from $2
+           for update"#,
+        module_id,
+        order.parent_id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+
+    // the new order must contain each sibling exactly once
+    let children: HashSet<Uuid> = order.children.iter().copied().collect();
+    if children.len() != order.children.len() || children != siblings {
+        return Err(ApiError(
+            anyhow!("Children must list every sibling exactly once"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    if let Some(change_set_id) = draft.change_set {
+        for (position, id) in order.children.iter().enumerate() {
+            let sort_order = Some(position as i32 + 1);
+            let concept = change_set::current(&mut tx, change_set_id, *id).await?;
+            if let Some(mut concept) = concept.filter(|c| c.sort_order != sort_order) {
+                concept.sort_order = sort_order;
+                change_set::save_draft(&mut tx, change_set_id, &user, &concept).await?;
             }
-            None => tree.push(c.into()),
         }
+        tx.commit().await?;
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    sqlx::query!(
+        r#"update concepts set sort_order = o.position::integer
+           from unnest($1::uuid[]) with ordinality o(id, position)
+           where concepts.id = o.id"#,
+        &order.children
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replaces published concepts by their drafts, keeping their position, and appends new
+/// drafts in their order, sorted like the tree by the database. Concepts moved to another
+/// module by their draft are removed.
+fn with_drafts(concepts: Vec<Concept>, drafts: Vec<Concept>, module_id: Uuid) -> Vec<Concept> {
+    let published: HashSet<Uuid> = concepts.iter().map(|c| c.id).collect();
+    let (updated, created): (Vec<Concept>, Vec<Concept>) =
+        drafts.into_iter().partition(|d| published.contains(&d.id));
+    let mut updated: HashMap<Uuid, Concept> = updated.into_iter().map(|d| (d.id, d)).collect();
+    let mut result: Vec<Concept> = concepts
+        .into_iter()
+        .map(|c| updated.remove(&c.id).unwrap_or(c))
+        .collect();
+    result.extend(created);
+    result.retain(|c| c.module_id == module_id);
+
+    result
+}
+
+fn localize(concepts: &mut [Concept], languages: &[String]) {
+    if languages.is_empty() {
+        return;
+    }
+    for c in concepts {
+        c.localize(languages);
+    }
+}
+
+/// Builds the concept tree of a module. Siblings keep the order of `concepts`, orphaned
+/// concepts are marked as such. `lint` reports their causes.
+#[tracing::instrument(skip_all, fields(concepts = concepts.len()))]
+fn build_concept_tree(concepts: Vec<Concept>) -> Vec<ConceptTree> {
+    let (mut tree, orphans) = nest(concepts);
+
+    if !orphans.is_empty() {
+        tracing::warn!(
+            "Orphaned concepts (missing parent or parent cycle) added as roots: {:?}",
+            orphans
+        );
+        for node in tree.iter_mut().filter(|n| orphans.contains(&n.id)) {
+            node.orphaned = true;
+        }
+    }
+    tree
+}
+
+#[tracing::instrument(skip_all, fields(concepts = concepts.len()))]
+fn to_tree(concepts: Vec<Concept>) -> Vec<ConceptTree> {
+    // concepts without their parent in the result are expected here
+    nest(concepts).0
+}
+
+/// Nests concepts below their parents regardless of input order. Siblings (and roots)
+/// keep their relative input order.
+///
+/// Returns the root nodes, followed by the subtrees of concepts whose parent is
+/// not part of `concepts` or which are part of a parent cycle, and the ids of those
+/// orphaned subtree roots. Every concept ends up in the tree exactly once.
+#[tracing::instrument(skip_all, fields(concepts = concepts.len()))]
+fn nest(concepts: Vec<Concept>) -> (Vec<ConceptTree>, Vec<Uuid>) {
+    let by_id: HashMap<Uuid, &Concept> = concepts.iter().map(|c| (c.id, c)).collect();
+    let mut children: HashMap<Uuid, Vec<&Concept>> = HashMap::new();
+    for c in &concepts {
+        if let Some(parent_id) = c.parent_id {
+            children.entry(parent_id).or_default().push(c);
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut tree: Vec<ConceptTree> = concepts
+        .iter()
+        .filter(|c| c.parent_id.is_none())
+        .map(|c| subtree(c, &children, &mut visited))
+        .collect();
+
+    let mut orphans = vec![];
+    for c in &concepts {
+        if visited.contains(&c.id) {
+            continue;
+        }
+        // climb up to the topmost unvisited ancestor, so orphaned subtrees stay intact
+        let mut top = c;
+        let mut path = HashSet::from([top.id]);
+        while let Some(parent) = top.parent_id.and_then(|id| by_id.get(&id)) {
+            if !path.insert(parent.id) {
+                // parent cycle
+                break;
+            }
+            top = *parent;
+        }
+
+        orphans.push(top.id);
+        tree.push(subtree(top, &children, &mut visited));
+    }
+
+    (tree, orphans)
+}
+
+fn subtree(
+    concept: &Concept,
+    children: &HashMap<Uuid, Vec<&Concept>>,
+    visited: &mut HashSet<Uuid>,
+) -> ConceptTree {
+    visited.insert(concept.id);
+
+    let mut node: ConceptTree = concept.into();
+    if let Some(cs) = children.get(&concept.id) {
+        for c in cs {
+            if !visited.contains(&c.id) {
+                node.children.push(subtree(c, children, visited));
+            }
+        }
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::tests::anonymous_config;
+    use crate::concept::SearchResult::Tree;
+    use crate::concept::{
+        build_concept_tree, router, CodingLookup, CodingMatch, CodingRef, Concept, ConceptTree,
+        Move, Search, SiblingOrder, StatusCode,
+    };
+    use crate::config::{AppConfig, Validation, ValidationMode};
+    use crate::server::ApiContext;
+    use crate::tree_cache;
+    use axum::body::Body;
+    use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH};
+    use axum::http::{HeaderName, Method, Request};
+    use axum::response::Response;
+    use axum::{http, Router};
+    use http_body_util::BodyExt;
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn builds_nested_concepts() {
+        let c1 = Concept {
+            id: Uuid::new_v4(),
+            ..Concept::default()
+        };
+        let c2 = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(c1.id),
+            ..Concept::default()
+        };
+        let c3 = Concept {
+            id: Uuid::new_v4(),
+            ..Concept::default()
+        };
+        let c4 = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(c2.id),
+            ..Concept::default()
+        };
+
+        // act
+        let result = build_concept_tree(vec![c1.clone(), c2, c3, c4.clone()]);
+
+        // check deeply nested
+        let nested = result
+            .iter()
+            // c2
+            .find(|c| c.id == c1.id)
+            .unwrap()
+            // nested child
+            .children
+            .first()
+            .unwrap()
+            // nested child
+            .children
+            .first()
+            .unwrap();
+
+        // two root elements
+        assert_eq!(result.len(), 2);
+        assert_eq!(nested.id, c4.id);
+    }
+
+    #[test]
+    fn builds_children_before_parents() {
+        let root = Concept {
+            id: Uuid::new_v4(),
+            ..Concept::default()
+        };
+        let child = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(root.id),
+            ..Concept::default()
+        };
+        let grandchild = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(child.id),
+            ..Concept::default()
+        };
+
+        // act
+        let result = build_concept_tree(vec![grandchild.clone(), child, root.clone()]);
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].id, root.id);
+        assert_eq!(result[0].children[0].children[0].id, grandchild.id);
+    }
+
+    #[test]
+    fn keeps_orphaned_concepts() {
+        let root = Concept {
+            id: Uuid::new_v4(),
+            ..Concept::default()
+        };
+        // parent in another module
+        let orphan = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(Uuid::new_v4()),
+            ..Concept::default()
+        };
+        let orphan_child = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(orphan.id),
+            ..Concept::default()
+        };
+        // parent cycle
+        let cycle_a_id = Uuid::new_v4();
+        let cycle_b = Concept {
+            id: Uuid::new_v4(),
+            parent_id: Some(cycle_a_id),
+            ..Concept::default()
+        };
+        let cycle_a = Concept {
+            id: cycle_a_id,
+            parent_id: Some(cycle_b.id),
+            ..Concept::default()
+        };
+
+        // act
+        let result = build_concept_tree(vec![
+            orphan_child.clone(),
+            cycle_a,
+            root.clone(),
+            orphan.clone(),
+            cycle_b,
+        ]);
+
+        // regular root first, orphaned subtrees are appended as marked roots
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].id, root.id);
+        assert!(!result[0].orphaned);
+        assert_eq!(result[1].id, orphan.id);
+        assert!(result[1].orphaned);
+        assert_eq!(result[1].children[0].id, orphan_child.id);
+        assert!(!result[1].children[0].orphaned);
+        assert!(result[2].orphaned);
+        assert_eq!(result[2].children.len(), 1);
+        assert!(result[2].children[0].children.is_empty());
+    }
+
+    /// Random forests with some dangling parent references, as (parent index, dangling) pairs
+    fn arb_concepts() -> impl Strategy<Value = Vec<Concept>> {
+        prop::collection::vec(
+            (any::<prop::sample::Index>(), prop::bool::weighted(0.1)),
+            1..60,
+        )
+        .prop_map(|nodes| {
+            let mut concepts: Vec<Concept> = vec![];
+            for (i, (parent, dangling)) in nodes.into_iter().enumerate() {
+                // index i (the concept itself) denotes a root
+                let parent = parent.index(i + 1);
+                let parent_id = if dangling {
+                    Some(Uuid::new_v4())
+                } else {
+                    concepts.get(parent).map(|p| p.id)
+                };
+                concepts.push(Concept {
+                    id: Uuid::new_v4(),
+                    parent_id,
+                    ..Concept::default()
+                });
+            }
+            concepts
+        })
+        .prop_shuffle()
+    }
+
+    fn flatten(tree: &[ConceptTree], ids: &mut Vec<Uuid>) {
+        for node in tree {
+            for child in &node.children {
+                assert_eq!(child.parent_id, Some(node.id));
+            }
+            ids.push(node.id);
+            flatten(&node.children, ids);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn builds_tree_in_any_order(concepts in arb_concepts()) {
+            let expected: HashSet<Uuid> = concepts.iter().map(|c| c.id).collect();
+            let roots = concepts.iter().filter(|c| c.parent_id.is_none()).count();
+
+            let tree = build_concept_tree(concepts);
+
+            // every concept exactly once, nested below its parent
+            let mut ids = vec![];
+            flatten(&tree, &mut ids);
+            prop_assert_eq!(ids.len(), expected.len());
+            prop_assert_eq!(ids.into_iter().collect::<HashSet<_>>(), expected);
+            prop_assert!(tree[..roots].iter().all(|t| t.parent_id.is_none()));
+        }
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn read_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        let response = send_request(
+            router,
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = parse_json(response).await.unwrap();
+
+        assert_eq!(
+            body,
+            json!({
+              "id": "a52b1865-9011-fe8a-deb1-12ce01327a2d",
+              "display": "Vancomycin",
+              "parent_id": "ce3e2ac8-6da7-4b36-7e7d-57a628022aca",
+              "module_id": "4bfd4e2e-caf5-f7ae-3ef8-400ab0858ec7",
+              "term_codes": [
+                {
+                  "code": "VANC",
+                  "system": "https://fhir.diz.uni-marburg.de/CodeSystem/swisslab-code",
+                  "display": "Vancomycin",
+                  "version": null
+                },
+                {
+                  "code": "20578-1",
+                  "system": "http://loinc.org",
+                  "display": "Vancomycin [Mass/volume] in Serum or Plasma",
+                  "version": "2.73"
+                }
+              ],
+              "leaf": true,
+              "time_restriction_allowed": true,
+              "filter_type": null,
+              "selectable": true,
+              "filter_options": null,
+              "version": "2.2.0",
+              "sort_order": null,
+              "designations": [
+                {"language": "en", "use": "preferred", "value": "Vancomycin (serum)"},
+                {"language": "de", "use": "synonym", "value": "Glykopeptid-Antibiotikum"},
+                {"language": "de", "use": "abbreviation", "value": "Vanco"}
+              ],
+              "description": null,
+              "definition": null,
+              "inclusion_note": null,
+              "exclusion_note": null,
+              "links": null
+            })
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn read_localized_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        for (uri, accept_language, display) in [
+            ("?lang=en", "de", "Vancomycin (serum)"),
+            ("", "fr;q=0.5, en-US;q=0.8", "Vancomycin (serum)"),
+            ("", "fr, de", "Vancomycin"),
+            ("?lang=de", "en", "Vancomycin"),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::GET)
+                        .uri(format!(
+                            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d{uri}"
+                        ))
+                        .header(http::header::ACCEPT_LANGUAGE, accept_language)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let concept = parse_concept(response).await.unwrap();
+            assert_eq!(concept.display, display);
+        }
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn update_or_create_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        let response = send_request(
+            router.clone(),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+
+        // current (old) concept
+        let old: Concept = parse_concept(response).await.unwrap();
+        // new version
+        let new = Concept {
+            version: "42".to_owned(),
+            ..old
+        };
+
+        // update
+        send_request(
+            router.clone(),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::PUT,
+            Body::from(serde_json::to_string(&new).unwrap()),
+        )
+        .await;
+
+        // check current version
+        let response = send_request(
+            router.clone(),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+        let current: Concept = parse_concept(response).await.unwrap();
+
+        assert_eq!(json!(current), json!(new))
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn shared_codings_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let uri = "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned();
+        let response = send_request(router.clone(), uri.clone(), Method::GET, Body::empty()).await;
+        let vancomycin = parse_json(response).await.unwrap();
+
+        // same codings, another display
+        let id = Uuid::new_v4();
+        let mut copy = vancomycin.clone();
+        copy["id"] = json!(id);
+        copy["term_codes"][1]["display"] = json!("Vancomycin Serum");
+        let copy_uri = format!("/ontology/concepts/{id}");
+        let response = send_request(
+            router.clone(),
+            copy_uri.clone(),
+            Method::PUT,
+            Body::from(copy.to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = send_request(router.clone(), copy_uri, Method::GET, Body::empty()).await;
+        assert_eq!(
+            parse_json(response).await.unwrap()["term_codes"][1]["display"],
+            json!("Vancomycin Serum")
+        );
+        let response = send_request(router, uri, Method::GET, Body::empty()).await;
+        assert_eq!(
+            parse_json(response).await.unwrap()["term_codes"],
+            vancomycin["term_codes"]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn update_validates_codings_test(pool: PgPool) {
+        let lenient = setup_router(pool.clone());
+        let response = send_request(
+            lenient.clone(),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+
+        // typo in code system url
+        let mut concept = parse_concept(response).await.unwrap();
+        if let Some(codes) = concept.term_codes.as_mut() {
+            codes[1].system = "http://loinc.or".to_owned();
+        }
+        let body = serde_json::to_string(&concept).unwrap();
+
+        // strict
+        let config = AppConfig {
+            validation: Validation {
+                mode: ValidationMode::Strict,
+            },
+            ..anonymous_config()
+        };
+        let state = Arc::new(ApiContext::new(pool, config).unwrap());
+        let response = send_request(
+            router().with_state(state),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::PUT,
+            Body::from(body.clone()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            parse_json(response).await.unwrap()["detail"],
+            "Invalid codings: Unknown code system: http://loinc.or"
+        );
+
+        // lenient
+        let response = send_request(
+            lenient,
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            Method::PUT,
+            Body::from(body),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn ontology_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        let response = send_request(
+            router,
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = parse_json(response).await.unwrap();
+
+        assert_eq!(body.pointer("/0/display").unwrap(), &json!("Medikamente"));
+        assert_eq!(
+            body.pointer("/0/children/0/children/0/display").unwrap(),
+            &json!("Voriconazol [Fremdlabor]")
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn ontology_metadata_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let voriconazol = "/0/children/0/children/0";
+
+        // left off by default
+        let response = send_request(
+            router.clone(),
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+        let body: Value = parse_json(response).await.unwrap();
+        let node = body.pointer(voriconazol).unwrap();
+        assert!(node.get("description").is_none());
+        assert!(node.get("links").is_none());
+
+        let response = send_request(
+            router,
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7?include=metadata".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+        let body: Value = parse_json(response).await.unwrap();
+        let node = body.pointer(voriconazol).unwrap();
+        assert_eq!(
+            node["inclusion_note"],
+            json!("Nur Ergebnisse aus Fremdlaboren")
+        );
+        assert_eq!(
+            node["links"],
+            json!([{"title": "LOINC 38370-3", "url": "https://loinc.org/38370-3"}])
+        );
+        assert!(node.get("definition").is_none());
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn move_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let voriconazol = "6f12427c7db35328e268206113ac1c69";
+
+        // Vancomycin below Voriconazol
+        let target = Move {
+            parent_id: Some(Uuid::parse_str(voriconazol).unwrap()),
+        };
+        let response = send_request(
+            router.clone(),
+            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d/move".to_owned(),
+            Method::POST,
+            Body::from(serde_json::to_string(&target).unwrap()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let moved = parse_concept(response).await.unwrap();
+        assert_eq!(moved.parent_id, target.parent_id);
+
+        // Voriconazol (with Vancomycin) below Medikamente
+        let target = Move {
+            parent_id: Some(Uuid::parse_str("6a0c97ad28afc3e3a8da9416e6936ce8").unwrap()),
+        };
+        let response = send_request(
+            router.clone(),
+            format!("/ontology/concepts/{}/move", voriconazol),
+            Method::POST,
+            Body::from(serde_json::to_string(&target).unwrap()),
+        )
+        .await;
+
+        let moved = parse_concept(response).await.unwrap();
+        // new parent of Vancomycin
+        assert!(!moved.leaf);
+        assert_eq!(moved.sort_order, None);
+
+        // Antibiotika has no children left
+        let response = send_request(
+            router,
+            "/ontology/concepts/ce3e2ac86da74b367e7d57a628022aca".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
+        let old_parent = parse_concept(response).await.unwrap();
+        assert!(old_parent.leaf);
     }
-    tree
-}
 
-fn to_tree(concepts: Vec<Concept>) -> Vec<ConceptTree> {
-    let mut tree: Vec<ConceptTree> = concepts.iter().map(|c| c.into()).collect();
-    // reorder children with existing parents
-    let reorder = tree
-        .clone()
-        .into_iter()
-        .filter(|c| {
-            tree.iter()
-                .any(|o| c.parent_id.is_some() && o.id == c.parent_id.unwrap())
-        })
-        .collect::<Vec<ConceptTree>>();
+    #[sqlx::test(fixtures("concepts"))]
+    async fn move_fails_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        let cases = [
+            // Antibiotika below its child Vancomycin
+            (
+                "ce3e2ac86da74b367e7d57a628022aca",
+                Some("a52b18659011fe8adeb112ce01327a2d"),
+                StatusCode::BAD_REQUEST,
+            ),
+            // Antibiotika below itself
+            (
+                "ce3e2ac86da74b367e7d57a628022aca",
+                Some("ce3e2ac86da74b367e7d57a628022aca"),
+                StatusCode::BAD_REQUEST,
+            ),
+            // Vancomycin into the diagnosis module
+            (
+                "a52b18659011fe8adeb112ce01327a2d",
+                Some("7ebd739d-d203-2fb4-7c78-8e753e69b507"),
+                StatusCode::BAD_REQUEST,
+            ),
+            // unknown concept
+            (
+                "00000000000000000000000000000000",
+                None,
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+
+        for (id, parent_id, status) in cases {
+            let target = Move {
+                parent_id: parent_id.map(|p| Uuid::parse_str(p).unwrap()),
+            };
+            let response = send_request(
+                router.clone(),
+                format!("/ontology/concepts/{}/move", id),
+                Method::POST,
+                Body::from(serde_json::to_string(&target).unwrap()),
+            )
+            .await;
 
-    // remove children from flat vector
-    tree.retain(|c| !reorder.contains(c));
-    for c in reorder {
-        // add child to its parent
-        tree.iter_mut().any(|t| t.add_child_to_tree(&c));
+            assert_eq!(response.status(), status);
+        }
     }
 
-    tree
-}
+    #[sqlx::test(fixtures("concepts"))]
+    async fn update_rejects_cycle_test(pool: PgPool) {
+        let router = setup_router(pool);
 
-#[cfg(test)]
-mod tests {
-    use crate::concept::SearchResult::Tree;
-    use crate::concept::{build_concept_tree, router, Concept, Search, StatusCode};
-    use crate::server::ApiContext;
-    use axum::body::Body;
-    use axum::http::{Method, Request};
-    use axum::response::Response;
-    use axum::{http, Router};
-    use http_body_util::BodyExt;
-    use serde_json::{json, Value};
-    use sqlx::PgPool;
-    use std::sync::Arc;
-    use tower::ServiceExt;
-    use uuid::Uuid;
+        let response = send_request(
+            router.clone(),
+            "/ontology/concepts/ce3e2ac86da74b367e7d57a628022aca".to_owned(),
+            Method::GET,
+            Body::empty(),
+        )
+        .await;
 
-    #[test]
-    fn builds_nested_concepts() {
-        let c1 = Concept {
-            id: Uuid::new_v4(),
-            ..Concept::default()
-        };
-        let c2 = Concept {
-            id: Uuid::new_v4(),
-            parent_id: Some(c1.id),
-            ..Concept::default()
-        };
-        let c3 = Concept {
-            id: Uuid::new_v4(),
-            ..Concept::default()
-        };
-        let c4 = Concept {
-            id: Uuid::new_v4(),
-            parent_id: Some(c2.id),
-            ..Concept::default()
+        // Antibiotika below its child Vancomycin
+        let concept = Concept {
+            parent_id: Some(Uuid::parse_str("a52b18659011fe8adeb112ce01327a2d").unwrap()),
+            ..parse_concept(response).await.unwrap()
         };
+        let response = send_request(
+            router,
+            "/ontology/concepts/ce3e2ac86da74b367e7d57a628022aca".to_owned(),
+            Method::PUT,
+            Body::from(serde_json::to_string(&concept).unwrap()),
+        )
+        .await;
 
-        // act
-        let result = build_concept_tree(vec![c1.clone(), c2, c3, c4.clone()]);
-
-        // check deeply nested
-        let nested = result
-            .iter()
-            // c2
-            .find(|c| c.id == c1.id)
-            .unwrap()
-            // nested child
-            .children
-            .first()
-            .unwrap()
-            // nested child
-            .children
-            .first()
-            .unwrap();
-
-        // two root elements
-        assert_eq!(result.len(), 2);
-        assert_eq!(nested.id, c4.id);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[sqlx::test(fixtures("concepts"))]
-    async fn read_test(pool: PgPool) {
+    async fn reorder_test(pool: PgPool) {
         let router = setup_router(pool);
 
+        // Antibiotika: Vancomycin before Voriconazol
+        let order = SiblingOrder {
+            parent_id: Some(Uuid::parse_str("ce3e2ac86da74b367e7d57a628022aca").unwrap()),
+            children: vec![
+                Uuid::parse_str("a52b18659011fe8adeb112ce01327a2d").unwrap(),
+                Uuid::parse_str("6f12427c7db35328e268206113ac1c69").unwrap(),
+            ],
+        };
+
+        let response = send_request(
+            router.clone(),
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/order".to_owned(),
+            Method::PUT,
+            Body::from(serde_json::to_string(&order).unwrap()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
         let response = send_request(
             router,
-            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7".to_owned(),
             Method::GET,
             Body::empty(),
         )
         .await;
-
-        assert_eq!(response.status(), StatusCode::OK);
-
-        let body = parse_json(response).await.unwrap();
+        let body: Value = parse_json(response).await.unwrap();
 
         assert_eq!(
-            body,
-            json!({
-              "id": "a52b1865-9011-fe8a-deb1-12ce01327a2d",
-              "display": "Vancomycin",
-              "parent_id": "ce3e2ac8-6da7-4b36-7e7d-57a628022aca",
-              "module_id": "4bfd4e2e-caf5-f7ae-3ef8-400ab0858ec7",
-              "term_codes": [
-                {
-                  "code": "VANC",
-                  "system": "https://fhir.diz.uni-marburg.de/CodeSystem/swisslab-code",
-                  "display": "Vancomycin",
-                  "version": null
-                },
-                {
-                  "code": "20578-1",
-                  "system": "http://loinc.org",
-                  "display": "Vancomycin [Mass/volume] in Serum or Plasma",
-                  "version": "2.73"
-                }
-              ],
-              "leaf": true,
-              "time_restriction_allowed": true,
-              "filter_type": null,
-              "selectable": true,
-              "filter_options": null,
-              "version": "2.2.0"
-            })
+            body.pointer("/0/children/0/children/0/display").unwrap(),
+            &json!("Vancomycin")
+        );
+        assert_eq!(
+            body.pointer("/0/children/0/children/1/sort_order").unwrap(),
+            &json!(2)
         );
     }
 
     #[sqlx::test(fixtures("concepts"))]
-    async fn update_or_create_test(pool: PgPool) {
+    async fn reorder_fails_test(pool: PgPool) {
         let router = setup_router(pool);
 
+        // missing sibling
+        let order = SiblingOrder {
+            parent_id: Some(Uuid::parse_str("ce3e2ac86da74b367e7d57a628022aca").unwrap()),
+            children: vec![Uuid::parse_str("a52b18659011fe8adeb112ce01327a2d").unwrap()],
+        };
+
         let response = send_request(
-            router.clone(),
-            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            router,
+            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/order".to_owned(),
+            Method::PUT,
+            Body::from(serde_json::to_string(&order).unwrap()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn conditional_requests_test(pool: PgPool) {
+        let router = setup_router(pool);
+        let tree = "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7";
+        let concept = "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d";
+        let send = |method: Method, uri: &str, header: Option<(HeaderName, &str)>, body: Body| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            router.clone().oneshot(request.body(body).unwrap())
+        };
+        let etag = |response: &Response| response.headers()[ETAG].to_str().unwrap().to_owned();
+
+        // tree
+        let response = send(Method::GET, tree, None, Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CACHE_CONTROL], "private, no-cache");
+        let tree_etag = etag(&response);
+        let response = send(
             Method::GET,
+            tree,
+            Some((IF_NONE_MATCH, &tree_etag)),
             Body::empty(),
         )
-        .await;
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(etag(&response), tree_etag);
+        // other languages are other representations
+        let response = send(
+            Method::GET,
+            &format!("{tree}?lang=en"),
+            Some((IF_NONE_MATCH, &tree_etag)),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        // current (old) concept
-        let old: Concept = parse_concept(response).await.unwrap();
-        // new version
+        // concept
+        let response = send(Method::GET, concept, None, Body::empty())
+            .await
+            .unwrap();
+        let concept_etag = etag(&response);
+        let old = parse_concept(response).await.unwrap();
+        let response = send(
+            Method::GET,
+            concept,
+            Some((IF_NONE_MATCH, &concept_etag)),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // update by the first editor
         let new = Concept {
             version: "42".to_owned(),
+            ..old.clone()
+        };
+        let response = send(
+            Method::PUT,
+            concept,
+            Some((IF_MATCH, &concept_etag)),
+            Body::from(serde_json::to_string(&new).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the second editor read the old concept
+        let stale = Concept {
+            version: "43".to_owned(),
             ..old
         };
+        let response = send(
+            Method::PUT,
+            concept,
+            Some((IF_MATCH, &concept_etag)),
+            Body::from(serde_json::to_string(&stale).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(Method::GET, concept, None, Body::empty())
+            .await
+            .unwrap();
+        assert_ne!(etag(&response), concept_etag);
+        assert_eq!(parse_concept(response).await.unwrap().version, "42");
 
-        // update
-        send_request(
-            router.clone(),
-            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+        // the tree changed with the concept
+        let response = send(
+            Method::GET,
+            tree,
+            Some((IF_NONE_MATCH, &tree_etag)),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(etag(&response), tree_etag);
+
+        // tags of localized reads are checked against the same representation
+        let localized = format!("{concept}?lang=en");
+        let response = send(Method::GET, &localized, None, Body::empty())
+            .await
+            .unwrap();
+        let localized_etag = etag(&response);
+        let body = serde_json::to_string(&parse_concept(response).await.unwrap()).unwrap();
+        let response = send(
             Method::PUT,
-            Body::from(serde_json::to_string(&new).unwrap()),
+            concept,
+            Some((IF_MATCH, &localized_etag)),
+            Body::from(body.clone()),
         )
-        .await;
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(
+            Method::PUT,
+            &localized,
+            Some((IF_MATCH, &localized_etag)),
+            Body::from(body),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 
-        // check current version
+    #[sqlx::test(fixtures("concepts"))]
+    async fn tree_cache_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool.clone(), anonymous_config()).unwrap());
+        tokio::spawn(tree_cache::listen(pool, state.trees.clone()));
+        let router = router().with_state(state.clone());
+        let tree = "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7";
+        let concept = "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d";
+        let read_tree = || async {
+            let response =
+                send_request(router.clone(), tree.to_owned(), Method::GET, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let wait_for = |trees: usize| {
+            let state = state.clone();
+            async move {
+                for _ in 0..100 {
+                    if state.trees.len() == trees {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                panic!("{} cached trees expected", trees);
+            }
+        };
+
+        // cached once listening
+        let mut cached = String::new();
+        for _ in 0..100 {
+            cached = read_tree().await;
+            if state.trees.len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        wait_for(1).await;
+        assert_eq!(read_tree().await, cached);
+
+        // invalidated by the notification of the update
         let response = send_request(
             router.clone(),
-            "/ontology/concepts/a52b18659011fe8adeb112ce01327a2d".to_owned(),
+            concept.to_owned(),
             Method::GET,
             Body::empty(),
         )
         .await;
-        let current: Concept = parse_concept(response).await.unwrap();
-
-        assert_eq!(json!(current), json!(new))
+        let updated = Concept {
+            display: "Vancomycin (updated)".to_owned(),
+            ..parse_concept(response).await.unwrap()
+        };
+        let response = send_request(
+            router.clone(),
+            concept.to_owned(),
+            Method::PUT,
+            Body::from(serde_json::to_string(&updated).unwrap()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        wait_for(0).await;
+        assert!(read_tree().await.contains("Vancomycin (updated)"));
     }
 
     #[sqlx::test(fixtures("concepts"))]
-    async fn ontology_test(pool: PgPool) {
+    async fn lookup_test(pool: PgPool) {
         let router = setup_router(pool);
 
         let response = send_request(
             router,
-            "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7".to_owned(),
+            "/ontology/concepts?system=http://loinc.org&code=20578-1".to_owned(),
             Method::GET,
             Body::empty(),
         )
@@ -454,13 +2130,76 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body: Value = parse_json(response).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let matches: Vec<CodingMatch> = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(body.pointer("/0/display").unwrap(), &json!("Medikamente"));
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].concept.display, "Vancomycin");
+        assert_eq!(matches[0].module.name, "Laboruntersuchung");
         assert_eq!(
-            body.pointer("/0/children/0/children/0/display").unwrap(),
-            &json!("Voriconazol [Fremdlabor]")
+            matches[0]
+                .ancestors
+                .iter()
+                .map(|a| a.display.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Medikamente", "Antibiotika"]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn lookup_batch_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        let codings = vec![
+            CodingRef {
+                system: "http://fhir.de/CodeSystem/bfarm/icd-10-gm".to_owned(),
+                code: "Q50".to_owned(),
+            },
+            CodingRef {
+                system: "http://loinc.org".to_owned(),
+                code: "0000-0".to_owned(),
+            },
+            CodingRef {
+                system: "https://fhir.diz.uni-marburg.de/CodeSystem/swisslab-code".to_owned(),
+                code: "VORI".to_owned(),
+            },
+        ];
+
+        let response = send_request(
+            router,
+            "/ontology/concepts/lookup".to_owned(),
+            Method::POST,
+            Body::from(serde_json::to_string(&codings).unwrap()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let result: Vec<CodingLookup> = serde_json::from_slice(&body).unwrap();
+
+        // in request order, including codings without matches
+        assert_eq!(
+            result
+                .iter()
+                .map(|l| (
+                    l.code.as_str(),
+                    l.concepts
+                        .iter()
+                        .map(|m| m.concept.display.as_str())
+                        .collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Q50",
+                    vec!["Angeborene Fehlbildungen der Ovarien, der Tubae uterinae und der Ligg. lata uteri"]
+                ),
+                ("0000-0", vec![]),
+                ("VORI", vec!["Voriconazol [Fremdlabor]"]),
+            ]
         );
+        assert_eq!(result[0].concepts[0].ancestors.len(), 2);
     }
 
     #[sqlx::test(fixtures("concepts"))]
@@ -511,11 +2250,42 @@ mod tests {
               "filter_type": null,
               "selectable": true,
               "filter_options": null,
-              "version": "2.2.0"
+              "version": "2.2.0",
+              "sort_order": 1,
+              "designations": null
             }])
         );
     }
 
+    #[sqlx::test(fixtures("concepts"))]
+    async fn search_designations_test(pool: PgPool) {
+        let router = setup_router(pool);
+
+        // synonym, displayed in english
+        let search = Search {
+            module_id: Uuid::parse_str("4bfd4e2ecaf5f7ae3ef8400ab0858ec7").unwrap(),
+            search_term: "glykopeptid".to_owned(),
+            display: None,
+        };
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/ontology/concepts/search?lang=en")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&search).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = parse_json(response).await.unwrap();
+        let results = body.as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["display"], "Vancomycin (serum)");
+    }
+
     #[sqlx::test(fixtures("concepts"))]
     async fn search_tree_test(pool: PgPool) {
         let router = setup_router(pool);
@@ -559,6 +2329,8 @@ mod tests {
             "selectable": true,
             "filter_options": null,
             "version": "2.2.0",
+            "sort_order": null,
+            "designations": null,
             "children": [
               {
                 "id": "f8f46412-df1f-42ee-6eca-845452fa507d",
@@ -578,7 +2350,9 @@ mod tests {
                 "filter_type": null,
                 "selectable": true,
                 "filter_options": null,
-                "version": "2.2.0"
+                "version": "2.2.0",
+                "sort_order": null,
+                "designations": null
               }
             ]
                     }])
@@ -606,9 +2380,12 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        let body = parse_string(response).await.unwrap();
+        let body = parse_json(response).await.unwrap();
 
-        assert_eq!(body, "Search term must consist of at least 2 characters");
+        assert_eq!(
+            body["detail"],
+            "Search term must consist of at least 2 characters"
+        );
     }
 
     async fn send_request(router: Router, uri: String, method: Method, body: Body) -> Response {
@@ -626,7 +2403,7 @@ mod tests {
     }
 
     fn setup_router(pool: PgPool) -> Router {
-        let state = Arc::new(ApiContext { db: pool });
+        let state = Arc::new(ApiContext::new(pool, anonymous_config()).unwrap());
         router().with_state(state)
     }
 
@@ -635,10 +2412,6 @@ mod tests {
         serde_json::from_slice(&body).map_err(|e| e.into())
     }
 
-    async fn parse_string(response: Response) -> Result<String, anyhow::Error> {
-        let body = response.into_body().collect().await?.to_bytes().to_vec();
-        String::from_utf8(body).map_err(|e| e.into())
-    }
     async fn parse_concept(response: Response) -> Result<Concept, anyhow::Error> {
         let body = response.into_body().collect().await?.to_bytes();
         serde_json::from_slice(&body).map_err(|e| e.into())
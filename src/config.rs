@@ -1,9 +1,56 @@
-use config::{Config, ConfigError, Environment, File};
+use config::{Config, ConfigError, Environment, File, Map};
 use serde_derive::Deserialize;
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub(crate) struct App {
     pub(crate) log_level: String,
+    #[serde(default)]
+    pub(crate) log_format: LogFormat,
+    /// log each request with its status, duration and caller
+    #[serde(default)]
+    pub(crate) access_log: bool,
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    #[default]
+    Text,
+    /// one JSON object per line, e.g. for log pipelines
+    Json,
+}
+
+/// Listener of the web API
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct Server {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// listen on a Unix domain socket instead of host and port
+    pub(crate) unix_socket: Option<String>,
+    pub(crate) tls: Option<Tls>,
+    /// seconds to drain in-flight requests on shutdown
+    pub(crate) shutdown_timeout: u64,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            host: "0.0.0.0".to_owned(),
+            port: 3000,
+            unix_socket: None,
+            tls: None,
+            shutdown_timeout: 30,
+        }
+    }
+}
+
+/// TLS certificate and private key (PEM), reloaded on SIGHUP
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Tls {
+    /// certificate chain, leaf certificate first
+    pub(crate) cert_file: String,
+    pub(crate) key_file: String,
 }
 
 #[derive(Default, Deserialize, Clone)]
@@ -13,20 +60,202 @@ pub(crate) struct Database {
     pub(crate) timeout: Option<u64>,
 }
 
+#[derive(Default, Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ValidationMode {
+    /// reject invalid concepts
+    Strict,
+    /// log validation problems as warnings
+    #[default]
+    Lenient,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct Validation {
+    pub(crate) mode: ValidationMode,
+}
+
+/// Identity provider groups/roles mapped to the permissions of the API
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct RoleMapping {
+    pub(crate) reader: Vec<String>,
+    pub(crate) editor: Vec<String>,
+    pub(crate) admin: Vec<String>,
+}
+
+impl Default for RoleMapping {
+    fn default() -> Self {
+        RoleMapping {
+            reader: vec!["mdr-reader".to_owned()],
+            editor: vec!["mdr-editor".to_owned()],
+            admin: vec!["mdr-admin".to_owned()],
+        }
+    }
+}
+
+/// Permission of anonymous callers if authentication is disabled
+#[derive(Default, Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AnonymousRole {
+    #[default]
+    Reader,
+    Editor,
+    Admin,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct Auth {
+    /// anonymous access with `anonymous_role` if disabled
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// read-only unless configured, e.g. `admin` for local development
+    #[serde(default)]
+    pub(crate) anonymous_role: AnonymousRole,
+    pub(crate) issuer: Option<String>,
+    pub(crate) audience: Option<String>,
+    /// JSON Web Key Set of the identity provider
+    pub(crate) jwks_file: Option<String>,
+    /// dotted path of the roles claim, e.g. `realm_access.roles`
+    pub(crate) roles_claim: Option<String>,
+    #[serde(default)]
+    pub(crate) roles: RoleMapping,
+    /// dotted path of the groups claim for module access control, e.g. `groups`
+    pub(crate) groups_claim: Option<String>,
+}
+
+/// Cross-origin resource sharing, no cross-origin requests are allowed by default
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct Cors {
+    /// allow any origin, method and header, e.g. for development
+    #[serde(default)]
+    pub(crate) permissive: bool,
+    /// e.g. `https://fdpm.example.org` or `https://*.example.org` for subdomains
+    #[serde(default)]
+    pub(crate) allowed_origins: Vec<String>,
+    /// defaults to `GET, POST, PUT, DELETE`
+    #[serde(default)]
+    pub(crate) allowed_methods: Vec<String>,
+    /// defaults to `Authorization, Content-Type, Accept-Language, If-Match`
+    #[serde(default)]
+    pub(crate) allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub(crate) allow_credentials: bool,
+    /// seconds preflight responses may be cached
+    pub(crate) max_age: Option<u64>,
+}
+
+/// Export of traces to an OpenTelemetry collector via OTLP/HTTP
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct Telemetry {
+    pub(crate) enabled: bool,
+    /// traces endpoint, defaults to `OTEL_EXPORTER_OTLP_ENDPOINT` or `http://localhost:4318/v1/traces`
+    pub(crate) endpoint: Option<String>,
+    /// `service.name` of the exported spans
+    pub(crate) service: String,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Telemetry {
+            enabled: false,
+            endpoint: None,
+            service: env!("CARGO_PKG_NAME").to_owned(),
+        }
+    }
+}
+
+/// In-process cache of built trees, invalidated by notifications of changed modules
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct Cache {
+    pub(crate) enabled: bool,
+    /// maximum number of cached trees, each module is cached by language and included parts
+    pub(crate) trees: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            enabled: true,
+            trees: 256,
+        }
+    }
+}
+
 #[derive(Default, Deserialize, Clone)]
 pub(crate) struct AppConfig {
     pub(crate) app: App,
+    #[serde(default)]
+    pub(crate) server: Server,
     pub(crate) database: Database,
+    #[serde(default)]
+    pub(crate) validation: Validation,
+    #[serde(default)]
+    pub(crate) auth: Auth,
+    #[serde(default)]
+    pub(crate) cors: Cors,
+    #[serde(default)]
+    pub(crate) telemetry: Telemetry,
+    #[serde(default)]
+    pub(crate) cache: Cache,
 }
 
 impl AppConfig {
     pub(crate) fn new() -> Result<Self, ConfigError> {
+        Self::load(None)
+    }
+
+    /// Reads `app.yaml` and the overrides of the environment, or of `env` if given
+    fn load(env: Option<Map<String, String>>) -> Result<Self, ConfigError> {
         Config::builder()
             // default config from file
             .add_source(File::with_name("app.yaml"))
-            // override values from environment variables
-            .add_source(Environment::default().separator("_"))
+            // override values from environment variables, e.g. `DATABASE_URL`. Keys containing
+            // underscores cannot be set this way.
+            .add_source(Environment::default().separator("_").source(env.clone()))
+            // e.g. `MDR_SERVER__UNIX_SOCKET` for `server.unix_socket`
+            .add_source(
+                Environment::with_prefix("MDR")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .source(env),
+            )
             .build()?
             .try_deserialize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_test() {
+        let env = Map::from([
+            ("DATABASE_URL".to_owned(), "postgres://db/mdr".to_owned()),
+            ("MDR_SERVER__PORT".to_owned(), "8443".to_owned()),
+            (
+                "MDR_SERVER__UNIX_SOCKET".to_owned(),
+                "/run/mdr.sock".to_owned(),
+            ),
+            (
+                "MDR_SERVER__TLS__CERT_FILE".to_owned(),
+                "cert.pem".to_owned(),
+            ),
+            ("MDR_SERVER__TLS__KEY_FILE".to_owned(), "key.pem".to_owned()),
+            ("MDR_AUTH__ANONYMOUS_ROLE".to_owned(), "editor".to_owned()),
+        ]);
+        let config = AppConfig::load(Some(env)).unwrap();
+
+        assert_eq!(config.database.url, "postgres://db/mdr");
+        assert_eq!(config.server.port, 8443);
+        assert_eq!(config.server.unix_socket.as_deref(), Some("/run/mdr.sock"));
+        let tls = config.server.tls.unwrap();
+        assert_eq!(
+            (tls.cert_file.as_str(), tls.key_file.as_str()),
+            ("cert.pem", "key.pem")
+        );
+        assert_eq!(config.auth.anonymous_role, AnonymousRole::Editor);
+    }
+}
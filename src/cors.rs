@@ -0,0 +1,204 @@
+use crate::config::Cors;
+use crate::telemetry::REQUEST_ID;
+use anyhow::{anyhow, bail};
+use axum::http::header::{ACCEPT_LANGUAGE, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH};
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Builds the CORS layer from the config. Without allowed origins, cross-origin requests
+/// are not allowed.
+pub(crate) fn layer(config: &Cors) -> anyhow::Result<CorsLayer> {
+    if config.permissive {
+        tracing::warn!("CORS is permissive, any origin is allowed");
+        return Ok(CorsLayer::permissive());
+    }
+    if config.allow_credentials && config.allowed_origins.iter().any(|o| o == "*") {
+        bail!("cors.allowed_origins must not contain '*' if credentials are allowed");
+    }
+
+    let methods = if config.allowed_methods.is_empty() {
+        vec![Method::GET, Method::POST, Method::PUT, Method::DELETE]
+    } else {
+        config
+            .allowed_methods
+            .iter()
+            .map(|m| Method::from_bytes(m.to_uppercase().as_bytes()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow!("Invalid cors.allowed_methods: {}", e))?
+    };
+    let headers = if config.allowed_headers.is_empty() {
+        vec![AUTHORIZATION, CONTENT_TYPE, ACCEPT_LANGUAGE, IF_MATCH]
+    } else {
+        config
+            .allowed_headers
+            .iter()
+            .map(|h| HeaderName::from_bytes(h.as_bytes()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow!("Invalid cors.allowed_headers: {}", e))?
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(allow_origin(&config.allowed_origins))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers([REQUEST_ID, ETAG])
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = config.max_age {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+
+    Ok(layer)
+}
+
+fn allow_origin(origins: &[String]) -> AllowOrigin {
+    if origins.iter().any(|o| o == "*") {
+        return AllowOrigin::any();
+    }
+    let origins = origins.to_vec();
+    AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+        origin
+            .to_str()
+            .is_ok_and(|origin| origins.iter().any(|allowed| matches(allowed, origin)))
+    })
+}
+
+/// Matches an origin against an allowed origin, which may contain a `*` for any
+/// subdomain, e.g. `https://*.example.org`.
+fn matches(allowed: &str, origin: &str) -> bool {
+    match allowed.split_once("*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|host| host.strip_suffix(domain))
+            .and_then(|host| host.strip_suffix('.'))
+            .is_some_and(|sub| {
+                !sub.is_empty()
+                    && sub
+                        .split('.')
+                        .all(|label| !label.is_empty() && label.chars().all(is_label_char))
+            }),
+        None => allowed.eq_ignore_ascii_case(origin),
+    }
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use axum::response::Response;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn config() -> Cors {
+        Cors {
+            allowed_origins: vec![
+                "https://fdpm.example.org".to_owned(),
+                "https://*.diz.example.org".to_owned(),
+            ],
+            allowed_methods: vec!["get".to_owned(), "put".to_owned()],
+            allow_credentials: true,
+            max_age: Some(600),
+            ..Cors::default()
+        }
+    }
+
+    async fn preflight(config: &Cors, origin: &str) -> Response {
+        let router = Router::new()
+            .route("/ontology/modules", get(|| async { "" }))
+            .layer(layer(config).unwrap());
+
+        router
+            .oneshot(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/ontology/modules")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+                    .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn allowed_origin(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|h| h.to_str().unwrap())
+    }
+
+    #[test]
+    fn matches_origins() {
+        let allowed = "https://*.example.org";
+        assert!(matches(allowed, "https://fdpm.example.org"));
+        assert!(matches(allowed, "https://a.b.example.org"));
+        assert!(!matches(allowed, "https://example.org"));
+        assert!(!matches(allowed, "http://fdpm.example.org"));
+        assert!(!matches(allowed, "https://evil-example.org"));
+        assert!(!matches(allowed, "https://fdpm.example.org.evil.com"));
+        assert!(!matches(allowed, "https://evil.com/.example.org"));
+        assert!(matches(
+            "https://fdpm.example.org",
+            "https://FDPM.example.org"
+        ));
+        assert!(!matches(
+            "https://fdpm.example.org",
+            "https://fdpm.example.org:8443"
+        ));
+    }
+
+    #[tokio::test]
+    async fn allows_preflight() {
+        for origin in ["https://fdpm.example.org", "https://mdr.diz.example.org"] {
+            let response = preflight(&config(), origin).await;
+            let headers = response.headers();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(allowed_origin(&response), Some(origin));
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+            assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET,PUT");
+            assert_eq!(
+                headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+                "authorization,content-type,accept-language,if-match"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_preflight() {
+        for origin in ["https://evil.example.org", "https://diz.example.org"] {
+            let response = preflight(&config(), origin).await;
+            assert_eq!(allowed_origin(&response), None, "{}", origin);
+        }
+
+        // no cross-origin requests by default
+        let response = preflight(&Cors::default(), "https://fdpm.example.org").await;
+        assert_eq!(allowed_origin(&response), None);
+    }
+
+    #[tokio::test]
+    async fn permissive_only_if_enabled() {
+        let permissive = Cors {
+            permissive: true,
+            ..Cors::default()
+        };
+        let response = preflight(&permissive, "https://evil.example.org").await;
+        assert_eq!(allowed_origin(&response), Some("*"));
+
+        let invalid = Cors {
+            allowed_origins: vec!["*".to_owned()],
+            allow_credentials: true,
+            ..Cors::default()
+        };
+        assert!(layer(&invalid).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use axum::http::header::ACCEPT_LANGUAGE;
+use axum::http::HeaderMap;
+use serde_derive::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+use utoipa::{IntoParams, ToSchema};
+
+/// Additional representation of a concept, e.g. a translation or synonym
+#[derive(Deserialize, Serialize, ToSchema, Clone, Debug, PartialEq)]
+pub(crate) struct Designation {
+    /// language tag (BCP 47), e.g. `en` or `de-CH`
+    pub(crate) language: String,
+    pub(crate) r#use: DesignationUse,
+    pub(crate) value: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DesignationUse {
+    /// display in the designation's language
+    Preferred,
+    Synonym,
+    Abbreviation,
+}
+
+impl DesignationUse {
+    fn as_str(self) -> &'static str {
+        match self {
+            DesignationUse::Preferred => "preferred",
+            DesignationUse::Synonym => "synonym",
+            DesignationUse::Abbreviation => "abbreviation",
+        }
+    }
+}
+
+/// Language parameter of read endpoints
+#[derive(Deserialize, Serialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct Lang {
+    /// display language, e.g. `en`, defaults to the `Accept-Language` header
+    pub(crate) lang: Option<String>,
+}
+
+/// Replaces the designations of a concept, keeping their order.
+pub(crate) async fn save_designations(
+    conn: &mut PgConnection,
+    concept_id: Uuid,
+    designations: &[Designation],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"delete from concept_designations where concept_id = $1"#,
+        concept_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    if designations.is_empty() {
+        return Ok(());
+    }
+
+    let languages: Vec<&str> = designations.iter().map(|d| d.language.as_str()).collect();
+    let uses: Vec<&str> = designations.iter().map(|d| d.r#use.as_str()).collect();
+    let values: Vec<&str> = designations.iter().map(|d| d.value.as_str()).collect();
+
+    sqlx::query!(
+        r#"insert into concept_designations (concept_id, language, use, value, position)
+           select $1, language, use, value, position
+           from unnest($2::text[], $3::text[], $4::text[]) with ordinality d(language, use, value, position)"#,
+        concept_id,
+        &languages as &[&str],
+        &uses as &[&str],
+        &values as &[&str]
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Requested languages in order of preference: the `lang` parameter, if present,
+/// otherwise the languages of the `Accept-Language` header by quality.
+pub(crate) fn languages(lang: &Lang, headers: &HeaderMap) -> Vec<String> {
+    if let Some(lang) = &lang.lang {
+        return vec![lang.to_lowercase()];
+    }
+    let Some(accept) = headers.get(ACCEPT_LANGUAGE).and_then(|h| h.to_str().ok()) else {
+        return vec![];
+    };
+
+    let mut weighted: Vec<(String, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let tag = parts.next().filter(|t| !t.is_empty() && *t != "*")?;
+            let quality = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (quality > 0.0).then(|| (tag.to_lowercase(), quality))
+        })
+        .collect();
+    // stable, keeps header order for equal quality
+    weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    weighted.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Preferred designation for the first matching language, exact language tags
+/// before tags of the same primary language (e.g. `en-GB` for `en-US`).
+pub(crate) fn preferred<'a>(
+    designations: &'a [Designation],
+    languages: &[String],
+) -> Option<&'a Designation> {
+    let primary = |tag: &str| tag.split('-').next().unwrap_or_default().to_lowercase();
+    let preferred = designations
+        .iter()
+        .filter(|d| d.r#use == DesignationUse::Preferred);
+
+    languages.iter().find_map(|lang| {
+        preferred
+            .clone()
+            .find(|d| d.language.eq_ignore_ascii_case(lang))
+            .or_else(|| {
+                preferred
+                    .clone()
+                    .find(|d| primary(&d.language) == primary(lang))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn designation(language: &str, r#use: DesignationUse, value: &str) -> Designation {
+        Designation {
+            language: language.to_owned(),
+            r#use,
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn negotiates_languages() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::from_static("fr;q=0.5, en-US, de;q=0.8, *;q=0.1, it;q=0"),
+        );
+
+        assert_eq!(
+            languages(&Lang::default(), &headers),
+            vec!["en-us", "de", "fr"]
+        );
+        assert_eq!(
+            languages(
+                &Lang {
+                    lang: Some("EN".to_owned())
+                },
+                &headers
+            ),
+            vec!["en"]
+        );
+        assert!(languages(&Lang::default(), &HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn selects_preferred_designation() {
+        let designations = vec![
+            designation("en", DesignationUse::Synonym, "Vanco"),
+            designation("en-GB", DesignationUse::Preferred, "Vancomycin (GB)"),
+            designation("en-US", DesignationUse::Preferred, "Vancomycin (US)"),
+            designation("fr", DesignationUse::Preferred, "Vancomycine"),
+        ];
+        let select = |langs: &[&str]| {
+            let langs: Vec<String> = langs.iter().map(|l| l.to_string()).collect();
+            preferred(&designations, &langs).map(|d| d.value.as_str())
+        };
+
+        assert_eq!(select(&["en-us"]), Some("Vancomycin (US)"));
+        assert_eq!(select(&["en"]), Some("Vancomycin (GB)"));
+        assert_eq!(select(&["de", "fr"]), Some("Vancomycine"));
+        assert_eq!(select(&["de"]), None);
+    }
+}
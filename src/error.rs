@@ -1,11 +1,92 @@
-use axum::http::StatusCode;
+use crate::telemetry::REQUEST_ID;
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+pub(crate) const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Error body size converted to problem details, larger bodies are dropped
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+#[derive(Debug)]
 pub(crate) struct ApiError(pub anyhow::Error, pub StatusCode);
 
+/// Problem details of an error response (RFC 7807)
+#[derive(Deserialize, Serialize, ToSchema, Debug, PartialEq)]
+pub(crate) struct Problem {
+    #[serde(rename = "type")]
+    pub(crate) problem_type: String,
+    pub(crate) title: String,
+    pub(crate) status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) detail: Option<String>,
+    /// path of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) request_id: Option<String>,
+}
+
+impl Problem {
+    pub(crate) fn new(status: StatusCode, detail: Option<String>) -> Self {
+        Problem {
+            problem_type: "about:blank".to_owned(),
+            title: status.canonical_reason().unwrap_or_default().to_owned(),
+            status: status.as_u16(),
+            detail,
+            instance: None,
+            request_id: None,
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, [(CONTENT_TYPE, PROBLEM_JSON)], Json(self)).into_response()
+    }
+}
+
+impl ApiError {
+    /// Status and detail of the response, database errors are mapped to their cause.
+    /// Internal details are logged only.
+    fn problem(&self) -> Problem {
+        if self.1 != StatusCode::INTERNAL_SERVER_ERROR {
+            return Problem::new(self.1, Some(format!("{}", self.0)));
+        }
+
+        let (status, detail) = match self.0.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                (StatusCode::CONFLICT, "Conflicts with an existing resource")
+            }
+            Some(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "References a missing resource",
+            ),
+            Some(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Database unavailable, please retry later",
+            ),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        };
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!("{:#}", self.0);
+        } else {
+            tracing::warn!("{:#}", self.0);
+        }
+        Problem::new(status, Some(detail.to_owned()))
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (self.1, format!("{}", self.0)).into_response()
+        self.problem().into_response()
     }
 }
 
@@ -17,3 +98,191 @@ where
         Self(err.into(), StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+/// Middleware adding the request path and id to problem details. Plain text error
+/// responses, e.g. of rejected extractors, are converted to problem details.
+pub(crate) async fn problems(request: Request, next: Next) -> Response {
+    let instance = request.uri().path().to_owned();
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .map(|id| id.to_owned());
+
+    let response = next.run(request).await;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let is_problem = content_type.starts_with(PROBLEM_JSON);
+    if !(status.is_client_error() || status.is_server_error())
+        || !(is_problem || content_type.is_empty() || content_type.starts_with("text/plain"))
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = to_bytes(body, MAX_ERROR_BODY).await.unwrap_or_default();
+    let mut problem = if is_problem {
+        serde_json::from_slice(&body).unwrap_or_else(|_| Problem::new(status, None))
+    } else {
+        let detail = String::from_utf8_lossy(&body).trim().to_owned();
+        // internal details of server errors are not returned
+        let detail = (status.is_client_error() && !detail.is_empty()).then_some(detail);
+        Problem::new(status, detail)
+    };
+    problem.instance = Some(instance);
+    problem.request_id = request_id;
+
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(CONTENT_LENGTH);
+    let body = serde_json::to_vec(&problem).unwrap_or_default();
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::anonymous_config;
+    use crate::module;
+    use crate::server::ApiContext;
+    use axum::http::Method;
+    use axum::middleware;
+    use axum::routing::get;
+    use axum::Router;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn send_request(
+        router: Router,
+        method: Method,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, String, Value) {
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(REQUEST_ID, "portal-42")
+                    .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn router(pool: PgPool) -> Router {
+        let state = Arc::new(ApiContext::new(pool, anonymous_config()).unwrap());
+        module::router()
+            .route(
+                "/failing",
+                get(|| async { Err::<(), _>(ApiError::from(anyhow::anyhow!("password=secret"))) }),
+            )
+            .with_state(state)
+            .layer(middleware::from_fn(problems))
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn problem_test(pool: PgPool) {
+        // api error
+        let (status, content_type, body) = send_request(
+            router(pool.clone()),
+            Method::GET,
+            "/ontology/modules/c2b1bd4e45a1d0b3d5b1b4f1e2a3c4d5",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(
+            body,
+            json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "No module found with id: c2b1bd4e-45a1-d0b3-d5b1-b4f1e2a3c4d5",
+                "instance": "/ontology/modules/c2b1bd4e45a1d0b3d5b1b4f1e2a3c4d5",
+                "request_id": "portal-42"
+            })
+        );
+
+        // rejected extractor
+        let (status, content_type, body) = send_request(
+            router(pool.clone()),
+            Method::GET,
+            "/ontology/modules/xyz",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(body["title"], "Bad Request");
+        assert!(body["detail"].as_str().unwrap().contains("UUID"));
+        assert_eq!(body["request_id"], "portal-42");
+
+        // internal details are not returned
+        let (status, _, body) =
+            send_request(router(pool.clone()), Method::GET, "/failing", Value::Null).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["detail"], "Internal server error");
+        assert!(!body.to_string().contains("secret"));
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn database_problem_test(pool: PgPool) {
+        let module = json!({
+            "id": "4bfd4e2ecaf5f7ae3ef8400ab0858ec7",
+            "name": "Laboruntersuchung",
+            "fdpg_cds_code": "Laboruntersuchung",
+            "fdpg_cds_system": "fdpg.mii.cds",
+            "fdpg_cds_version": "1.0.0",
+            "version": "2.2.0"
+        });
+        let (status, _, body) = send_request(
+            router(pool.clone()),
+            Method::POST,
+            "/ontology/modules",
+            module,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["detail"], "Conflicts with an existing resource");
+
+        // pool exhausted
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+        let _conn = pool.acquire().await.unwrap();
+        let (status, _, body) = send_request(
+            router(pool.clone()),
+            Method::GET,
+            "/ontology/modules",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["title"], "Service Unavailable");
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::ApiError;
+use crate::hex;
+use anyhow::anyhow;
+use axum::http::header::{
+    ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, VARY,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use ring::digest::{digest, Context, SHA256};
+use serde::Serialize;
+
+/// Reads depend on the caller, caches revalidate them with their entity tag on each use
+const CACHE_CONTROL_VALUE: &str = "private, no-cache";
+
+/// Strong entity tag of the parts of a representation, e.g. a revision and the display
+/// languages. Includes the service version, as representations may change between releases.
+pub(crate) fn entity_tag(parts: &[&[u8]]) -> String {
+    let mut context = Context::new(&SHA256);
+    context.update(env!("CARGO_PKG_VERSION").as_bytes());
+    for part in parts {
+        context.update(&[0]);
+        context.update(part);
+    }
+    format!("\"{}\"", hex::encode(&context.finish().as_ref()[..16]))
+}
+
+/// Strong entity tag of a serialized representation
+pub(crate) fn content_tag(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&digest(&SHA256, body).as_ref()[..16]))
+}
+
+/// Whether `If-None-Match` matches the entity tag, compared weakly (RFC 9110, 13.1.2)
+pub(crate) fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    tags(headers, IF_NONE_MATCH).is_some_and(|tags| {
+        tags.iter()
+            .any(|t| *t == "*" || t.trim_start_matches("W/") == etag)
+    })
+}
+
+/// Checks `If-Match` against the entity tag of the current representation, if any,
+/// compared strongly (RFC 9110, 13.1.1). Requests without the header are not checked.
+pub(crate) fn require_match(headers: &HeaderMap, etag: Option<&str>) -> Result<(), ApiError> {
+    let Some(tags) = tags(headers, IF_MATCH) else {
+        return Ok(());
+    };
+    let matched = etag.is_some_and(|etag| tags.iter().any(|t| *t == "*" || *t == etag));
+    if matched {
+        Ok(())
+    } else {
+        Err(ApiError(
+            anyhow!("The resource has been modified, reload it and retry"),
+            StatusCode::PRECONDITION_FAILED,
+        ))
+    }
+}
+
+fn tags(headers: &HeaderMap, name: HeaderName) -> Option<Vec<&str>> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+/// Response with validators: entity tag, cache control and the localizing header
+pub(crate) fn tagged(response: impl IntoResponse, etag: &str) -> Response {
+    let mut response = response.into_response();
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(ETAG, etag);
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(CACHE_CONTROL_VALUE));
+    headers.insert(VARY, HeaderValue::from_static(ACCEPT_LANGUAGE.as_str()));
+    response
+}
+
+/// JSON response tagged by its content, `304 Not Modified` if the caller's copy is current
+pub(crate) fn cached_json<T: Serialize>(
+    headers: &HeaderMap,
+    value: &T,
+) -> Result<Response, ApiError> {
+    let body = serde_json::to_vec(value)?;
+    let etag = content_tag(&body);
+    if not_modified(headers, &etag) {
+        return Ok(tagged(StatusCode::NOT_MODIFIED, &etag));
+    }
+    Ok(tagged(
+        ([(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())], body),
+        &etag,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn compares_tags() {
+        let etag = content_tag(b"[]");
+        assert_eq!(etag.len(), 34);
+        assert_ne!(entity_tag(&[b"1", b"de"]), entity_tag(&[b"1", b"en"]));
+        assert_ne!(entity_tag(&[b"1d", b"e"]), entity_tag(&[b"1", b"de"]));
+
+        assert!(not_modified(
+            &headers(IF_NONE_MATCH, &format!("\"x\", W/{etag}")),
+            &etag
+        ));
+        assert!(not_modified(&headers(IF_NONE_MATCH, "*"), &etag));
+        assert!(!not_modified(&headers(IF_NONE_MATCH, "\"x\""), &etag));
+        assert!(!not_modified(&HeaderMap::new(), &etag));
+
+        assert!(require_match(&HeaderMap::new(), None).is_ok());
+        assert!(require_match(&headers(IF_MATCH, &etag), Some(&etag)).is_ok());
+        assert!(require_match(&headers(IF_MATCH, "*"), Some(&etag)).is_ok());
+        let weak = require_match(&headers(IF_MATCH, &format!("W/{etag}")), Some(&etag));
+        assert_eq!(weak.unwrap_err().1, StatusCode::PRECONDITION_FAILED);
+        assert!(require_match(&headers(IF_MATCH, "*"), None).is_err());
+    }
+}
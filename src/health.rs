@@ -0,0 +1,197 @@
+use crate::auth::{Role, User};
+use crate::error::ApiError;
+use crate::server::{ApiContext, MIGRATOR};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{debug_handler, Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct Health {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<Checks>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct Checks {
+    database: Status,
+    migrations: Status,
+}
+
+/// Build and data of the running service
+#[derive(Deserialize, Serialize, Debug)]
+struct Info {
+    version: String,
+    git_commit: Option<String>,
+    /// latest applied migration
+    migration_version: Option<i64>,
+    modules: i64,
+    concepts: i64,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    Router::new()
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+        .route("/info", get(info))
+}
+
+/// The process is able to serve requests, regardless of the database.
+#[debug_handler]
+async fn live() -> Json<Health> {
+    Json(Health {
+        status: Status::Up,
+        checks: None,
+    })
+}
+
+/// The database is reachable and all migrations are applied.
+#[debug_handler]
+async fn ready(State(ctx): State<Arc<ApiContext>>) -> (StatusCode, Json<Health>) {
+    let (database, migrations) = match applied_migrations(&ctx.db).await {
+        Ok(applied) => {
+            let missing = MIGRATOR
+                .iter()
+                .filter(|m| m.migration_type.is_up_migration())
+                .filter(|m| !applied.contains(&m.version))
+                .count();
+            if missing == 0 {
+                (Status::Up, Status::Up)
+            } else {
+                tracing::warn!("Not ready: {} migrations not applied", missing);
+                (Status::Up, Status::Down)
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Not ready: {}", e);
+            (Status::Down, Status::Down)
+        }
+    };
+
+    let (status, code) = if database == Status::Up && migrations == Status::Up {
+        (Status::Up, StatusCode::OK)
+    } else {
+        (Status::Down, StatusCode::SERVICE_UNAVAILABLE)
+    };
+    let health = Health {
+        status,
+        checks: Some(Checks {
+            database,
+            migrations,
+        }),
+    };
+    (code, Json(health))
+}
+
+async fn applied_migrations(db: &PgPool) -> Result<HashSet<i64>, sqlx::Error> {
+    // the migrations table is not known at compile time
+    let versions: Vec<i64> =
+        sqlx::query_scalar("select version from _sqlx_migrations where success")
+            .fetch_all(db)
+            .await?;
+
+    Ok(versions.into_iter().collect())
+}
+
+#[debug_handler]
+async fn info(State(ctx): State<Arc<ApiContext>>, user: User) -> Result<Json<Info>, ApiError> {
+    user.require(Role::Reader)?;
+
+    let counts = sqlx::query!(
+        r#"select (select count(*) from modules) as "modules!",
+                  (select count(*) from concepts) as "concepts!""#
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+    let migration_version = applied_migrations(&ctx.db).await?.into_iter().max();
+
+    Ok(Json(Info {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git_commit: option_env!("GIT_COMMIT").map(|c| c.to_owned()),
+        migration_version,
+        modules: counts.modules,
+        concepts: counts.concepts,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn send_request(pool: PgPool, uri: &str) -> (StatusCode, Value) {
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let response = router()
+            .with_state(state)
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn health_test(pool: PgPool) {
+        let (status, body) = send_request(pool.clone(), "/health/live").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"status": "up"}));
+
+        let (status, body) = send_request(pool.clone(), "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"status": "up", "checks": {"database": "up", "migrations": "up"}})
+        );
+
+        let (status, body) = send_request(pool.clone(), "/info").await;
+        assert_eq!(status, StatusCode::OK);
+        let latest = MIGRATOR.iter().map(|m| m.version).max();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["migration_version"], json!(latest));
+        assert_eq!(body["modules"], 2);
+        assert!(body["concepts"].as_i64().unwrap() > 0);
+
+        // pending migration
+        sqlx::query("delete from _sqlx_migrations where version = $1")
+            .bind(latest)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (status, body) = send_request(pool.clone(), "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body,
+            json!({"status": "down", "checks": {"database": "up", "migrations": "down"}})
+        );
+
+        pool.close().await;
+        let (status, body) = send_request(pool, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["database"], "down");
+    }
+}
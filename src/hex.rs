@@ -0,0 +1,4 @@
+/// Lowercase hex encoding of bytes, e.g. of digests and random secrets
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
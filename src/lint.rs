@@ -0,0 +1,597 @@
+use crate::auth::{Role, User};
+use crate::coding::Coding;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::module::{self, Permission};
+use crate::server::{self, ApiContext};
+use anyhow::anyhow;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{debug_handler, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Rule {
+    /// Concept is part of a `parent_id` cycle
+    Cycle,
+    /// Parent concept belongs to another module
+    ForeignParent,
+    /// Concept is marked as leaf but has children
+    LeafWithChildren,
+    /// Selectable concept without term codes
+    MissingTermCodes,
+    /// Term code (system, code) is used by more than one concept, of any module
+    DuplicateCoding,
+    /// Term code or filter option is not a valid `Coding`, e.g. has a blank code or system
+    MalformedCoding,
+    /// Concept version differs from the module version
+    VersionMismatch,
+    /// Coding version differs from the prevalent version of its system in the module
+    CodingVersionMismatch,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, PartialEq)]
+pub(crate) struct Finding {
+    pub(crate) rule: Rule,
+    pub(crate) concept_id: Uuid,
+    pub(crate) message: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub(crate) struct Report {
+    pub(crate) module_id: Uuid,
+    pub(crate) findings: Vec<Finding>,
+}
+
+#[derive(Debug, Default)]
+struct LintConcept {
+    id: Uuid,
+    display: String,
+    parent_id: Option<Uuid>,
+    parent_module_id: Option<Uuid>,
+    leaf: bool,
+    has_children: bool,
+    selectable: bool,
+    term_codes: Option<Value>,
+    filter_options: Option<Value>,
+    version: String,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new().routes(routes!(lint))
+}
+
+/// Checks the concepts of a module for structural problems
+#[utoipa::path(
+    get,
+    path = "/ontology/modules/{id}/lint",
+    tag = "modules",
+    params(("id" = Uuid, Path, description = "Module id")),
+    responses((status = 200, description = "Findings of the module", body = Report))
+)]
+#[debug_handler]
+async fn lint(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Report>, ApiError> {
+    user.require(Role::Reader)?;
+    module::require_access(&ctx.db, &user, id, Permission::Read).await?;
+
+    match check_module(&ctx.db, id).await? {
+        Some(report) => Ok(Json(report)),
+        None => Err(ApiError(
+            anyhow!(format!("No module found with id: {}", id)),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+/// Checks one or all modules and prints the findings. Returns `false` if there are any.
+pub(crate) async fn run(config: AppConfig, module_id: Option<Uuid>) -> anyhow::Result<bool> {
+    let pool = server::connect(&config.database).await;
+
+    let module_ids = match module_id {
+        Some(id) => vec![id],
+        None => {
+            sqlx::query_scalar!(r#"select id from modules order by name"#)
+                .fetch_all(&pool)
+                .await?
+        }
+    };
+
+    let mut total = 0;
+    for id in module_ids {
+        let report = check_module(&pool, id)
+            .await?
+            .ok_or(anyhow!("No module found with id: {}", id))?;
+
+        for f in &report.findings {
+            println!(
+                "{}: {} [{}] {}",
+                report.module_id,
+                f.concept_id,
+                serde_json::to_value(f.rule)?.as_str().unwrap_or_default(),
+                f.message
+            );
+        }
+        total += report.findings.len();
+    }
+    println!("{} finding(s)", total);
+
+    Ok(total == 0)
+}
+
+async fn check_module(db: &PgPool, module_id: Uuid) -> Result<Option<Report>, sqlx::Error> {
+    let Some(version) =
+        sqlx::query_scalar!(r#"select version from modules where id = $1"#, module_id)
+            .fetch_optional(db)
+            .await?
+    else {
+        return Ok(None);
+    };
+
+    let concepts = sqlx::query_as!(
+        LintConcept,
+        r#"select c.id as "id!", c.display as "display!", c.parent_id,
+                  p.module_id as "parent_module_id?", c.leaf as "leaf!",
+                  exists(select 1 from concepts ch where ch.parent_id = c.id) as "has_children!",
+                  c.selectable as "selectable!", c.term_codes, c.filter_options, c.version as "version!"
+           from concepts_with_codings c
+           left join concepts p on p.id = c.parent_id
+           where c.module_id = $1
+           order by c.id"#,
+        module_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    // term codes of the module used by several concepts of all modules
+    let usages = sqlx::query!(
+        r#"select k.system, k.code, array_agg(distinct cc.concept_id) as "concept_ids!"
+           from concept_codings cc
+                    join codings k on k.id = cc.coding_id
+           where cc.kind = 'term_code'
+             and (k.system, k.code) in (select m.system, m.code
+                                        from concept_codings mc
+                                                 join codings m on m.id = mc.coding_id
+                                                 join concepts c on c.id = mc.concept_id
+                                        where c.module_id = $1
+                                          and mc.kind = 'term_code')
+           group by k.system, k.code
+           having count(distinct cc.concept_id) > 1"#,
+        module_id
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| ((r.system, r.code), r.concept_ids))
+    .collect();
+
+    Ok(Some(Report {
+        module_id,
+        findings: check(module_id, &version, &concepts, &usages),
+    }))
+}
+
+/// Checks the concepts of a module, `usages` holds the concepts of all modules sharing a term code
+fn check(
+    module_id: Uuid,
+    module_version: &str,
+    concepts: &[LintConcept],
+    usages: &HashMap<(String, String), Vec<Uuid>>,
+) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut report = |rule, concept: &LintConcept, message: String| {
+        findings.push(Finding {
+            rule,
+            concept_id: concept.id,
+            message: format!("{}: {}", concept.display, message),
+        })
+    };
+
+    let parents: HashMap<Uuid, Option<Uuid>> =
+        concepts.iter().map(|c| (c.id, c.parent_id)).collect();
+    // system -> version -> usages
+    let mut versions: HashMap<String, BTreeMap<Option<String>, usize>> = HashMap::new();
+    let mut codings: Vec<(&LintConcept, Coding)> = vec![];
+
+    for c in concepts {
+        if is_cyclic(c.id, &parents) {
+            report(Rule::Cycle, c, "concept is its own ancestor".to_owned());
+        }
+        if let Some(parent_module_id) = c.parent_module_id
+            && parent_module_id != module_id
+        {
+            report(
+                Rule::ForeignParent,
+                c,
+                format!(
+                    "parent {} is part of module {}",
+                    c.parent_id.unwrap_or_default(),
+                    parent_module_id
+                ),
+            );
+        }
+        if c.leaf && c.has_children {
+            report(
+                Rule::LeafWithChildren,
+                c,
+                "leaf concept has children".to_owned(),
+            );
+        }
+        if c.version != module_version {
+            report(
+                Rule::VersionMismatch,
+                c,
+                format!(
+                    "version {} differs from module version {}",
+                    c.version, module_version
+                ),
+            );
+        }
+
+        let term_codes = parse_codings(c.term_codes.as_ref());
+        if c.selectable && term_codes.as_ref().is_ok_and(|t| t.is_empty()) {
+            report(
+                Rule::MissingTermCodes,
+                c,
+                "selectable concept has no term codes".to_owned(),
+            );
+        }
+        for (field, parsed) in [
+            ("term_codes", &term_codes),
+            ("filter_options", &parse_codings(c.filter_options.as_ref())),
+        ] {
+            if let Err(e) = parsed {
+                report(
+                    Rule::MalformedCoding,
+                    c,
+                    format!("invalid {}: {}", field, e),
+                );
+            }
+        }
+
+        for coding in term_codes.unwrap_or_default() {
+            let key = (coding.system.clone(), coding.code.clone());
+            if let Some(other) = usages
+                .get(&key)
+                .and_then(|ids| ids.iter().find(|id| **id != c.id))
+            {
+                report(
+                    Rule::DuplicateCoding,
+                    c,
+                    format!("{}|{} is also used by concept {}", key.0, key.1, other),
+                );
+            }
+            *versions
+                .entry(coding.system.clone())
+                .or_default()
+                .entry(coding.version.clone())
+                .or_default() += 1;
+            codings.push((c, coding));
+        }
+    }
+
+    // prevalent version per system, ties resolved to the latest
+    let prevalent: HashMap<&String, &Option<String>> = versions
+        .iter()
+        .filter(|(_, v)| v.len() > 1)
+        .filter_map(|(system, v)| {
+            v.iter()
+                .max_by_key(|(version, count)| (**count, version.is_some()))
+                .map(|(version, _)| (system, version))
+        })
+        .collect();
+    for (c, coding) in codings {
+        if let Some(version) = prevalent.get(&coding.system)
+            && **version != coding.version
+        {
+            report(
+                Rule::CodingVersionMismatch,
+                c,
+                format!(
+                    "{}|{} has version {}, the module mostly uses {}",
+                    coding.system,
+                    coding.code,
+                    coding.version.as_deref().unwrap_or("none"),
+                    version.as_deref().unwrap_or("none")
+                ),
+            );
+        }
+    }
+
+    findings
+}
+
+/// Follows the parent chain of a concept and checks whether it leads back to the concept.
+fn is_cyclic(id: Uuid, parents: &HashMap<Uuid, Option<Uuid>>) -> bool {
+    let mut seen = HashSet::new();
+    let mut current = parents.get(&id).copied().flatten();
+    while let Some(parent_id) = current {
+        if parent_id == id {
+            return true;
+        }
+        if !seen.insert(parent_id) {
+            // cycle further up
+            return false;
+        }
+        current = parents.get(&parent_id).copied().flatten();
+    }
+    false
+}
+
+fn parse_codings(value: Option<&Value>) -> Result<Vec<Coding>, String> {
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
+    let codings: Vec<Coding> = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+    if let Some(c) = codings
+        .iter()
+        .find(|c| c.code.trim().is_empty() || c.system.trim().is_empty())
+    {
+        return Err(format!("empty code or system in {:?}", c));
+    }
+
+    Ok(codings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::{auth_config, group_token};
+    use axum::body::Body;
+    use axum::http::{self, Request};
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn finds_inconsistencies() {
+        let module_id = Uuid::new_v4();
+        let root = LintConcept {
+            id: Uuid::new_v4(),
+            leaf: true,
+            has_children: true,
+            term_codes: Some(json!([
+                {"code": "A", "system": "http://loinc.org", "display": "A", "version": "2.73"},
+            ])),
+            version: "1.0.0".to_owned(),
+            ..LintConcept::default()
+        };
+        let selectable = LintConcept {
+            id: Uuid::new_v4(),
+            parent_id: Some(root.id),
+            parent_module_id: Some(module_id),
+            selectable: true,
+            term_codes: Some(json!([])),
+            version: "1.0.0".to_owned(),
+            ..LintConcept::default()
+        };
+        let foreign = LintConcept {
+            id: Uuid::new_v4(),
+            parent_id: Some(Uuid::new_v4()),
+            parent_module_id: Some(Uuid::new_v4()),
+            term_codes: Some(json!([
+                {"code": "A", "system": "http://loinc.org", "display": "A", "version": "2.73"},
+                {"code": "B", "system": "http://loinc.org"},
+            ])),
+            version: "0.9.0".to_owned(),
+            ..LintConcept::default()
+        };
+        let duplicate = LintConcept {
+            id: Uuid::new_v4(),
+            term_codes: Some(json!([
+                {"code": "A", "system": "http://loinc.org", "display": "A", "version": "2.73"},
+                {"code": "C", "system": "http://loinc.org", "display": "C", "version": "2.73"},
+                {"code": "D", "system": "http://loinc.org", "display": "D", "version": "2.42"},
+            ])),
+            filter_options: Some(json!({"code": "E"})),
+            version: "1.0.0".to_owned(),
+            ..LintConcept::default()
+        };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let cycle_a = LintConcept {
+            id: a,
+            parent_id: Some(b),
+            parent_module_id: Some(module_id),
+            version: "1.0.0".to_owned(),
+            ..LintConcept::default()
+        };
+        let cycle_b = LintConcept {
+            id: b,
+            parent_id: Some(a),
+            parent_module_id: Some(module_id),
+            version: "1.0.0".to_owned(),
+            ..LintConcept::default()
+        };
+
+        let usages = HashMap::from([(
+            ("http://loinc.org".to_owned(), "A".to_owned()),
+            vec![root.id, duplicate.id],
+        )]);
+
+        let findings = check(
+            module_id,
+            "1.0.0",
+            &[root, selectable, foreign, duplicate, cycle_a, cycle_b],
+            &usages,
+        );
+        let rules: Vec<Rule> = findings.iter().map(|f| f.rule).collect();
+
+        assert_eq!(
+            rules,
+            vec![
+                Rule::LeafWithChildren,
+                Rule::DuplicateCoding,
+                Rule::MissingTermCodes,
+                Rule::ForeignParent,
+                Rule::VersionMismatch,
+                Rule::MalformedCoding,
+                Rule::MalformedCoding,
+                Rule::DuplicateCoding,
+                Rule::Cycle,
+                Rule::Cycle,
+                Rule::CodingVersionMismatch,
+            ]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn lint_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = router().with_state(state);
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/ontology/modules/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/lint")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: Report = serde_json::from_slice(&body).unwrap();
+
+        // Voriconazol uses an older LOINC version than Vancomycin
+        assert_eq!(
+            report.findings,
+            vec![Finding {
+                rule: Rule::CodingVersionMismatch,
+                concept_id: Uuid::parse_str("6f12427c7db35328e268206113ac1c69").unwrap(),
+                message: "Voriconazol [Fremdlabor]: http://loinc.org|38370-3 has version 2.42, \
+                          the module mostly uses 2.73"
+                    .to_owned(),
+            }]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn codings_test(pool: PgPool) {
+        // Vancomycin's LOINC code also names a diagnosis, Medikamente has a blank filter option
+        sqlx::query!(
+            r#"insert into concept_codings (concept_id, coding_id, kind, position, display)
+               values ('f8f46412-df1f-42ee-6eca-845452fa507d', 4, 'term_code', 2, 'Vancomycin')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"with blank as (insert into codings (system, code, display)
+                              values ('http://loinc.org', ' ', 'Blank') returning id)
+               insert into concept_codings (concept_id, coding_id, kind, position, display)
+               select '6a0c97ad28afc3e3a8da9416e6936ce8', id, 'filter_option', 1, 'Blank' from blank"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let lab = check_module(
+            &pool,
+            Uuid::parse_str("4bfd4e2ecaf5f7ae3ef8400ab0858ec7").unwrap(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let diagnose = check_module(
+            &pool,
+            Uuid::parse_str("f6d13ed9f9a1dd6042ee01f8c924a586").unwrap(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let rules: Vec<(Rule, String)> = lab
+            .findings
+            .iter()
+            .map(|f| (f.rule, f.concept_id.simple().to_string()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                (
+                    Rule::MalformedCoding,
+                    "6a0c97ad28afc3e3a8da9416e6936ce8".to_owned()
+                ),
+                (
+                    Rule::DuplicateCoding,
+                    "a52b18659011fe8adeb112ce01327a2d".to_owned()
+                ),
+                (
+                    Rule::CodingVersionMismatch,
+                    "6f12427c7db35328e268206113ac1c69".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            lab.findings[1].message,
+            "Vancomycin: http://loinc.org|20578-1 is also used by concept \
+             f8f46412-df1f-42ee-6eca-845452fa507d"
+        );
+        assert_eq!(
+            diagnose.findings,
+            vec![Finding {
+                rule: Rule::DuplicateCoding,
+                concept_id: Uuid::parse_str("f8f46412df1f42ee6eca845452fa507d").unwrap(),
+                message: "Angeborene Fehlbildungen der Ovarien, der Tubae uterinae und der Ligg. \
+                          lata uteri: http://loinc.org|20578-1 is also used by concept \
+                          a52b1865-9011-fe8a-deb1-12ce01327a2d"
+                    .to_owned(),
+            }]
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn restricted_test(pool: PgPool) {
+        sqlx::query!(
+            r#"insert into module_acls (module_id, group_name, permission)
+               values ('4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'lab', 'read')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        let router = router().with_state(state);
+
+        for (groups, status) in [
+            (&[] as &[&str], StatusCode::NOT_FOUND),
+            (&["lab"], StatusCode::OK),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::GET)
+                        .uri("/ontology/modules/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/lint")
+                        .header(
+                            http::header::AUTHORIZATION,
+                            group_token("alice", &["mdr-reader"], groups),
+                        )
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{:?}", groups);
+        }
+    }
+}
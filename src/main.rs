@@ -1,18 +1,91 @@
+mod api_key;
+mod auth;
+mod change_set;
+mod code_system;
+mod coding;
 mod concept;
 mod config;
+mod cors;
+mod designation;
+mod error;
+mod etag;
+mod health;
+mod hex;
+mod lint;
+mod metrics;
 mod module;
+mod openapi;
 mod server;
-mod error;
+mod shutdown;
+mod telemetry;
+mod terminology;
+mod tls;
+mod tree_cache;
 
 use crate::config::AppConfig;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use uuid::Uuid;
+
+#[derive(Parser)]
+#[command(version, about = "DIZ Marburg MDR Web API")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web API (default)
+    Serve,
+    /// Check the ontology for consistency, exits non-zero on findings
+    Lint {
+        /// Module to check, all modules if omitted
+        #[arg(long)]
+        module: Option<Uuid>,
+    },
+    /// Import a terminology release file for code validation and autocomplete
+    Import {
+        /// Format of the release file
+        #[arg(long, value_enum)]
+        format: terminology::Format,
+        /// Version of the release, e.g. 2.78 or 2025
+        #[arg(long)]
+        version: String,
+        /// Code system url, defaults to the canonical url of the format
+        #[arg(long)]
+        system: Option<String>,
+        /// Release file (Loinc.csv, ClaML XML or ATC text file)
+        file: PathBuf,
+    },
+}
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+
     // config
     let config = AppConfig::new().expect("Failed to load config");
 
-    // run
-    server::serve(config).await?;
+    match cli.command.unwrap_or(Command::Serve) {
+        // run
+        Command::Serve => server::serve(config).await?,
+        Command::Lint { module } => {
+            if !lint::run(config, module).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Import {
+            format,
+            version,
+            system,
+            file,
+        } => {
+            let count = terminology::run_import(config, format, &file, &version, system).await?;
+            println!("Imported {count} codes");
+        }
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,477 @@
+use crate::error::ApiError;
+use crate::server::ApiContext;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{debug_handler, Router};
+use sqlx::types::Uuid;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Request latency buckets in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// Tree build buckets in seconds
+const BUILD_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+/// Search result size buckets
+const SIZE_BUCKETS: &[f64] = &[0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0];
+
+/// Cumulative histogram
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &[(&str, &str)]) {
+        let labels = format_labels(labels);
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (bucket, count) in self.buckets.iter().zip(&self.counts) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bucket}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let braces = |labels: &str| {
+            if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{labels}}}")
+            }
+        };
+        let _ = writeln!(out, "{name}_sum{} {}", braces(&labels), self.sum);
+        let _ = writeln!(out, "{name}_count{} {}", braces(&labels), self.count);
+    }
+}
+
+/// Metrics of the API, exposed in Prometheus text format
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    /// by method, route and status
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// by method and route
+    durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    search_results: Mutex<Histogram>,
+    tree_builds: Mutex<Histogram>,
+    /// by hit
+    tree_cache: Mutex<BTreeMap<bool, u64>>,
+    /// concepts per module with the module revision they were counted at
+    module_concepts: Mutex<HashMap<Uuid, (i64, i64)>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            requests: Mutex::default(),
+            durations: Mutex::default(),
+            search_results: Mutex::new(Histogram::new(SIZE_BUCKETS)),
+            tree_builds: Mutex::new(Histogram::new(BUILD_BUCKETS)),
+            tree_cache: Mutex::default(),
+            module_concepts: Mutex::default(),
+        }
+    }
+}
+
+impl Metrics {
+    fn observe_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_owned(), route.to_owned(), status))
+            .or_default() += 1;
+        self.durations
+            .lock()
+            .unwrap()
+            .entry((method.to_owned(), route.to_owned()))
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn observe_search(&self, results: usize) {
+        self.search_results.lock().unwrap().observe(results as f64);
+    }
+
+    pub(crate) fn observe_tree_build(&self, duration: Duration) {
+        self.tree_builds
+            .lock()
+            .unwrap()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn observe_tree_cache(&self, hit: bool) {
+        *self.tree_cache.lock().unwrap().entry(hit).or_default() += 1;
+    }
+
+    fn write(&self, out: &mut String) {
+        header(
+            out,
+            "mdr_http_requests_total",
+            "counter",
+            "HTTP requests by method, route and status",
+        );
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let labels = format_labels(&[
+                ("method", method),
+                ("route", route),
+                ("status", &status.to_string()),
+            ]);
+            let _ = writeln!(out, "mdr_http_requests_total{{{labels}}} {count}");
+        }
+
+        header(
+            out,
+            "mdr_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by method and route",
+        );
+        for ((method, route), histogram) in self.durations.lock().unwrap().iter() {
+            histogram.write(
+                out,
+                "mdr_http_request_duration_seconds",
+                &[("method", method), ("route", route)],
+            );
+        }
+
+        header(
+            out,
+            "mdr_search_results",
+            "histogram",
+            "Number of concepts found by searches",
+        );
+        self.search_results
+            .lock()
+            .unwrap()
+            .write(out, "mdr_search_results", &[]);
+
+        header(
+            out,
+            "mdr_tree_build_duration_seconds",
+            "histogram",
+            "Duration of building ontology trees",
+        );
+        self.tree_builds
+            .lock()
+            .unwrap()
+            .write(out, "mdr_tree_build_duration_seconds", &[]);
+
+        header(
+            out,
+            "mdr_tree_cache_requests_total",
+            "counter",
+            "Tree requests by cache result",
+        );
+        for (hit, count) in self.tree_cache.lock().unwrap().iter() {
+            let result = if *hit { "hit" } else { "miss" };
+            let _ = writeln!(
+                out,
+                "mdr_tree_cache_requests_total{{result=\"{result}\"}} {count}"
+            );
+        }
+    }
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Middleware recording requests by their route, e.g. `/ontology/tree/{module_id}`
+pub(crate) async fn track(
+    State(ctx): State<Arc<ApiContext>>,
+    path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+    // unmatched paths are not recorded, they would be unbounded
+    if let Some(path) = path {
+        ctx.metrics.observe_request(
+            &method,
+            path.as_str(),
+            response.status().as_u16(),
+            started.elapsed(),
+        );
+    }
+    response
+}
+
+#[debug_handler]
+async fn metrics(State(ctx): State<Arc<ApiContext>>) -> Result<Response, ApiError> {
+    let mut out = String::new();
+    ctx.metrics.write(&mut out);
+
+    // pool
+    header(
+        &mut out,
+        "mdr_db_pool_connections",
+        "gauge",
+        "Open database connections",
+    );
+    let _ = writeln!(out, "mdr_db_pool_connections {}", ctx.db.size());
+    header(
+        &mut out,
+        "mdr_db_pool_idle_connections",
+        "gauge",
+        "Idle database connections",
+    );
+    let _ = writeln!(out, "mdr_db_pool_idle_connections {}", ctx.db.num_idle());
+    header(
+        &mut out,
+        "mdr_db_pool_max_connections",
+        "gauge",
+        "Maximum database connections",
+    );
+    let _ = writeln!(
+        out,
+        "mdr_db_pool_max_connections {}",
+        ctx.db.options().get_max_connections()
+    );
+    // tree cache
+    header(&mut out, "mdr_tree_cache_trees", "gauge", "Cached trees");
+    let _ = writeln!(out, "mdr_tree_cache_trees {}", ctx.trees.len());
+
+    // ontology, the endpoint is public: restricted modules are left out
+    let modules = sqlx::query!(
+        r#"select m.id, m.name, m.revision from modules m
+           where not exists (select from module_acls a where a.module_id = m.id)
+           order by m.name"#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    // only modules changed since the last scrape are counted again
+    let stale: Vec<Uuid> = {
+        let counted = ctx.metrics.module_concepts.lock().unwrap();
+        modules
+            .iter()
+            .filter(|m| counted.get(&m.id).is_none_or(|(r, _)| *r != m.revision))
+            .map(|m| m.id)
+            .collect()
+    };
+    let counts: HashMap<Uuid, i64> = if stale.is_empty() {
+        HashMap::new()
+    } else {
+        sqlx::query!(
+            r#"select module_id, count(*) as "concepts!" from concepts
+               where module_id = any($1) group by module_id"#,
+            &stale
+        )
+        .fetch_all(&ctx.db)
+        .await?
+        .into_iter()
+        .map(|r| (r.module_id, r.concepts))
+        .collect()
+    };
+    let mut counted = ctx.metrics.module_concepts.lock().unwrap();
+    counted.retain(|id, _| modules.iter().any(|m| m.id == *id));
+    for m in modules.iter().filter(|m| stale.contains(&m.id)) {
+        let concepts = counts.get(&m.id).copied().unwrap_or_default();
+        counted.insert(m.id, (m.revision, concepts));
+    }
+    header(
+        &mut out,
+        "mdr_module_concepts",
+        "gauge",
+        "Concepts per unrestricted module",
+    );
+    for m in &modules {
+        // removed by a concurrent scrape which no longer found the module
+        let Some((_, concepts)) = counted.get(&m.id) else {
+            continue;
+        };
+        let labels = format_labels(&[("module_id", &m.id.to_string()), ("module", &m.name)]);
+        let _ = writeln!(out, "mdr_module_concepts{{{labels}}} {concepts}");
+    }
+    drop(counted);
+
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        out,
+    )
+        .into_response())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concept;
+    use crate::config::AppConfig;
+    use axum::body::Body;
+    use axum::http::{self, Method, StatusCode};
+    use axum::middleware::from_fn_with_state;
+    use http_body_util::BodyExt;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    #[test]
+    fn writes_histograms() {
+        let mut histogram = Histogram::new(&[1.0, 5.0]);
+        histogram.observe(0.5);
+        histogram.observe(3.0);
+        histogram.observe(7.0);
+
+        let mut out = String::new();
+        histogram.write(&mut out, "size", &[("route", "/a\"b")]);
+        assert_eq!(
+            out,
+            "size_bucket{route=\"/a\\\"b\",le=\"1\"} 1\n\
+             size_bucket{route=\"/a\\\"b\",le=\"5\"} 2\n\
+             size_bucket{route=\"/a\\\"b\",le=\"+Inf\"} 3\n\
+             size_sum{route=\"/a\\\"b\"} 10.5\n\
+             size_count{route=\"/a\\\"b\"} 3\n"
+        );
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn metrics_test(pool: PgPool) {
+        let concepts: i64 = sqlx::query_scalar!(
+            r#"select count(*) as "count!" from concepts
+               where module_id = '4bfd4e2ecaf5f7ae3ef8400ab0858ec7'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"insert into module_acls (module_id, group_name, permission)
+               values ('f6d13ed9f9a1dd6042ee01f8c924a586', 'icd', 'read')"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = router()
+            .merge(concept::router())
+            .route_layer(from_fn_with_state(state.clone(), track))
+            .with_state(state.clone());
+
+        for (method, uri, body) in [
+            (
+                Method::GET,
+                "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7",
+                "",
+            ),
+            (
+                Method::POST,
+                "/ontology/concepts/search",
+                r#"{"module_id": "4bfd4e2ecaf5f7ae3ef8400ab0858ec7", "search_term": "vanco"}"#,
+            ),
+            (Method::GET, "/metrics", ""),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            if uri == "/metrics" {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let metrics = String::from_utf8(body.to_vec()).unwrap();
+
+                let module_concepts = format!(
+                    r#"mdr_module_concepts{{module_id="4bfd4e2e-caf5-f7ae-3ef8-400ab0858ec7",module="Laboruntersuchung"}} {}"#,
+                    concepts
+                );
+                for line in [
+                    r#"mdr_http_requests_total{method="GET",route="/ontology/tree/{module_id}",status="200"} 1"#,
+                    r#"mdr_http_request_duration_seconds_count{method="POST",route="/ontology/concepts/search"} 1"#,
+                    r#"mdr_search_results_bucket{le="1"} 1"#,
+                    "mdr_tree_build_duration_seconds_count 1",
+                    r#"mdr_tree_cache_requests_total{result="miss"} 1"#,
+                    &module_concepts,
+                ] {
+                    assert!(metrics.lines().any(|l| l == line), "{}\n{}", line, metrics);
+                }
+                assert!(metrics.contains("mdr_db_pool_connections "));
+                // restricted
+                assert!(!metrics.contains("Diagnose"), "{}", metrics);
+            }
+        }
+
+        // counted again once the module changed
+        sqlx::query!(
+            r#"insert into concepts (id, module_id, display, selectable, leaf, version)
+               values (gen_random_uuid(), '4bfd4e2ecaf5f7ae3ef8400ab0858ec7', 'Kupfer', true, true,
+                       '1.0.0')"#
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+        let response = router
+            .oneshot(
+                http::Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        let module_concepts = format!(
+            r#"mdr_module_concepts{{module_id="4bfd4e2e-caf5-f7ae-3ef8-400ab0858ec7",module="Laboruntersuchung"}} {}"#,
+            concepts + 1
+        );
+        assert!(metrics.lines().any(|l| l == module_concepts), "{}", metrics);
+    }
+}
@@ -1,35 +1,77 @@
+use crate::auth::{Role, User};
 use crate::error::ApiError;
+use crate::etag;
 use crate::server::ApiContext;
 use anyhow::anyhow;
 use axum::extract::Path;
-use axum::http::StatusCode;
-use axum::{debug_handler, extract::State, routing::get, Json, Router};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::{debug_handler, extract::State, Json, Router};
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
-use sqlx::FromRow;
+use sqlx::{FromRow, PgExecutor};
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
-#[derive(Deserialize, Serialize, FromRow, Debug, PartialEq)]
-struct Module {
-    id: Uuid,
-    name: String,
-    fdpg_cds_code: String,
-    fdpg_cds_system: String,
-    fdpg_cds_version: String,
-    version: String,
+/// CDS module, e.g. `Laboruntersuchung`
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, PartialEq, Clone)]
+pub(crate) struct Module {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) fdpg_cds_code: String,
+    pub(crate) fdpg_cds_system: String,
+    pub(crate) fdpg_cds_version: String,
+    pub(crate) version: String,
+}
+
+/// Access of a group to a restricted module, `write` includes `read`
+#[derive(
+    Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum Permission {
+    Read,
+    Write,
+}
+
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, PartialEq, Clone)]
+pub(crate) struct AclEntry {
+    group: String,
+    permission: Permission,
 }
 
 pub(crate) fn router() -> Router<Arc<ApiContext>> {
-    Router::new()
-        .route("/ontology/modules", get(all).post(create))
-        .route("/ontology/modules/{id}", get(read))
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new()
+        .routes(routes!(all, create))
+        .routes(routes!(read))
+        .routes(routes!(read_acl, update_acl))
 }
 
+/// Creates a module
+#[utoipa::path(
+    post,
+    path = "/ontology/modules",
+    tag = "modules",
+    request_body = Module,
+    responses((status = 201, description = "Created module", body = Module))
+)]
 #[debug_handler]
 async fn create(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
     module: Json<Module>,
 ) -> Result<(StatusCode, Json<Module>), ApiError> {
+    user.require(Role::Admin)?;
+
     let result = sqlx::query_as!(
         Module,
         r#"insert into modules (id,name,fdpg_cds_code,fdpg_cds_system,fdpg_cds_version,version)
@@ -48,24 +90,60 @@ async fn create(
     Ok((StatusCode::CREATED, Json(result)))
 }
 
+/// Lists the modules readable by the caller
+#[utoipa::path(
+    get,
+    path = "/ontology/modules",
+    tag = "modules",
+    responses(
+        (status = 200, description = "Modules", body = Vec<Module>),
+        (status = 304, description = "Not modified since the `If-None-Match` entity tag")
+    )
+)]
 #[debug_handler]
-async fn all(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Vec<Module>>, ApiError> {
-    let modules = sqlx::query_as!(
+async fn all(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    user.require(Role::Reader)?;
+
+    let hidden = restricted(&ctx.db, &user, Permission::Read).await?;
+    let modules: Vec<Module> = sqlx::query_as!(
         Module,
         r#"select id, name, fdpg_cds_code,fdpg_cds_system,fdpg_cds_version, version
            from modules"#
     )
     .fetch_all(&ctx.db)
-    .await?;
+    .await?
+    .into_iter()
+    .filter(|m| !hidden.contains(&m.id))
+    .collect();
 
-    Ok(Json(modules))
+    etag::cached_json(&headers, &modules)
 }
 
+/// Reads a module
+#[utoipa::path(
+    get,
+    path = "/ontology/modules/{id}",
+    tag = "modules",
+    params(("id" = Uuid, Path, description = "Module id")),
+    responses(
+        (status = 200, description = "Module", body = Module),
+        (status = 304, description = "Not modified since the `If-None-Match` entity tag")
+    )
+)]
 #[debug_handler]
 async fn read(
     State(ctx): State<Arc<ApiContext>>,
+    user: User,
     Path(id): Path<Uuid>,
-) -> Result<Json<Module>, ApiError> {
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    user.require(Role::Reader)?;
+    require_access(&ctx.db, &user, id, Permission::Read).await?;
+
     let result = sqlx::query_as!(
         Module,
         r#"select id, name, fdpg_cds_code,fdpg_cds_system,fdpg_cds_version, version
@@ -76,17 +154,164 @@ async fn read(
     .await?;
 
     match result {
-        Some(module) => Ok(Json(module)),
-        None => Err(ApiError(
-            anyhow!(format!("No module found with id: {}", id)),
-            StatusCode::NOT_FOUND,
+        Some(module) => etag::cached_json(&headers, &module),
+        None => Err(not_found(id)),
+    }
+}
+
+/// Reads the access control list of a module
+#[utoipa::path(
+    get,
+    path = "/ontology/modules/{id}/acl",
+    tag = "modules",
+    params(("id" = Uuid, Path, description = "Module id")),
+    responses((status = 200, description = "Access control list", body = Vec<AclEntry>))
+)]
+#[debug_handler]
+async fn read_acl(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<AclEntry>>, ApiError> {
+    user.require(Role::Admin)?;
+
+    let acl = sqlx::query_as!(
+        AclEntry,
+        r#"select group_name as "group", permission as "permission: Permission"
+           from module_acls where module_id = $1 order by group_name"#,
+        id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(acl))
+}
+
+/// Replaces the access control list of a module, an empty list removes all restrictions.
+#[utoipa::path(
+    put,
+    path = "/ontology/modules/{id}/acl",
+    tag = "modules",
+    params(("id" = Uuid, Path, description = "Module id")),
+    request_body = Vec<AclEntry>,
+    responses((status = 200, description = "Access control list", body = Vec<AclEntry>))
+)]
+#[debug_handler]
+async fn update_acl(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Path(id): Path<Uuid>,
+    Json(acl): Json<Vec<AclEntry>>,
+) -> Result<Json<Vec<AclEntry>>, ApiError> {
+    user.require(Role::Admin)?;
+
+    let mut tx = ctx.db.begin().await?;
+    sqlx::query_scalar!(r#"select id from modules where id = $1 for update"#, id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| not_found(id))?;
+
+    // highest permission of each group
+    let mut permissions: BTreeMap<String, Permission> = BTreeMap::new();
+    for entry in acl {
+        let permission = permissions.entry(entry.group).or_insert(entry.permission);
+        *permission = (*permission).max(entry.permission);
+    }
+    let (groups, permissions): (Vec<String>, Vec<Permission>) = permissions.into_iter().unzip();
+
+    sqlx::query!(r#"delete from module_acls where module_id = $1"#, id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        r#"insert into module_acls (module_id, group_name, permission)
+           select $1, group_name, permission
+           from unnest($2::text[], $3::text[]) a(group_name, permission)"#,
+        id,
+        &groups,
+        &permissions as &[Permission]
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let acl = groups
+        .into_iter()
+        .zip(permissions)
+        .map(|(group, permission)| AclEntry { group, permission })
+        .collect();
+    Ok(Json(acl))
+}
+
+/// Restricted modules the user lacks the permission for. Admins are not restricted.
+pub(crate) async fn restricted(
+    db: impl PgExecutor<'_>,
+    user: &User,
+    permission: Permission,
+) -> Result<HashSet<Uuid>, sqlx::Error> {
+    if user.has(Role::Admin) {
+        return Ok(HashSet::new());
+    }
+
+    let hidden = sqlx::query_scalar!(
+        r#"select module_id from module_acls
+           group by module_id
+           having not bool_or(group_name = any($1) and (permission = 'write' or $2 = 'read'))"#,
+        &user.groups,
+        permission as Permission
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(hidden.into_iter().collect())
+}
+
+/// Checks the user's access to a module. Modules the user cannot read are not found.
+pub(crate) async fn require_access(
+    db: impl PgExecutor<'_>,
+    user: &User,
+    module_id: Uuid,
+    permission: Permission,
+) -> Result<(), ApiError> {
+    if user.has(Role::Admin) {
+        return Ok(());
+    }
+
+    // null without access control entries
+    let access = sqlx::query!(
+        r#"select bool_or(group_name = any($2)) as readable,
+                  bool_or(group_name = any($2) and permission = 'write') as writable
+           from module_acls where module_id = $1"#,
+        module_id,
+        &user.groups
+    )
+    .fetch_one(db)
+    .await?;
+
+    match (access.readable, access.writable, permission) {
+        (None, _, _) | (Some(true), _, Permission::Read) | (_, Some(true), Permission::Write) => {
+            Ok(())
+        }
+        (Some(true), _, Permission::Write) => Err(ApiError(
+            anyhow!(format!("Permission denied for module {}", module_id)),
+            StatusCode::FORBIDDEN,
         )),
+        _ => Err(not_found(module_id)),
     }
 }
 
+fn not_found(id: Uuid) -> ApiError {
+    ApiError(
+        anyhow!(format!("No module found with id: {}", id)),
+        StatusCode::NOT_FOUND,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::tests::{anonymous_config, auth_config, group_token, token};
+    use crate::concept;
+    use crate::config::AppConfig;
     use axum::body::Body;
     use axum::http::{self, Request, StatusCode};
     use http_body_util::BodyExt;
@@ -96,7 +321,7 @@ mod tests {
 
     #[sqlx::test(fixtures("modules"))]
     async fn read_test(pool: PgPool) {
-        let state = Arc::new(ApiContext { db: pool });
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
         let router = router().with_state(state);
 
         let response = router
@@ -130,7 +355,7 @@ mod tests {
 
     #[sqlx::test(fixtures("modules"))]
     async fn all_test(pool: PgPool) {
-        let state = Arc::new(ApiContext { db: pool });
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
         let router = router().with_state(state);
 
         let response = router
@@ -171,7 +396,7 @@ mod tests {
 
     #[sqlx::test(fixtures("modules"))]
     async fn create_test(pool: PgPool) {
-        let state = Arc::new(ApiContext { db: pool });
+        let state = Arc::new(ApiContext::new(pool, anonymous_config()).unwrap());
         let router = router().with_state(state);
 
         let new_module = Module {
@@ -202,4 +427,156 @@ mod tests {
 
         assert_eq!(body, new_module);
     }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn acl_test(pool: PgPool) {
+        let change_set = Uuid::new_v4();
+        sqlx::query!(
+            r#"insert into change_sets (id, title, author) values ($1, 'Order', 'alice')"#,
+            change_set
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        let router = router().merge(concept::router()).with_state(state);
+        let lab = "4bfd4e2e-caf5-f7ae-3ef8-400ab0858ec7";
+        let acl_uri = format!("/ontology/modules/{lab}/acl");
+        let reorder_uri = format!("/ontology/tree/{lab}/order?change_set={change_set}");
+        let reorder = json!({"parent_id": "ce3e2ac86da74b367e7d57a628022aca",
+            "children": ["a52b18659011fe8adeb112ce01327a2d", "6f12427c7db35328e268206113ac1c69"]});
+        let search = json!({"module_id": lab, "search_term": "vanco"});
+
+        let admin = token("root", &["mdr-admin"]);
+        let (status, acl) = send_request(
+            &router,
+            http::Method::PUT,
+            &acl_uri,
+            &admin,
+            json!([{"group": "research", "permission": "read"},
+                   {"group": "lab", "permission": "read"},
+                   {"group": "lab", "permission": "write"}]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            acl,
+            json!([{"group": "lab", "permission": "write"},
+                   {"group": "research", "permission": "read"}])
+        );
+
+        // not listed or found without a group
+        let other = group_token("carol", &["mdr-editor"], &["icu"]);
+        let (_, modules) = send_request(
+            &router,
+            http::Method::GET,
+            "/ontology/modules",
+            &other,
+            Value::Null,
+        )
+        .await;
+        assert_eq!(modules.as_array().unwrap().len(), 1);
+        assert_eq!(modules[0]["name"], "Diagnose");
+        for (method, uri, body) in [
+            (
+                http::Method::GET,
+                format!("/ontology/modules/{lab}"),
+                Value::Null,
+            ),
+            (
+                http::Method::GET,
+                format!("/ontology/tree/{lab}"),
+                Value::Null,
+            ),
+            (
+                http::Method::POST,
+                "/ontology/concepts/search".to_owned(),
+                search.clone(),
+            ),
+        ] {
+            let (status, _) = send_request(&router, method, &uri, &other, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        // read only
+        let research = group_token("bob", &["mdr-editor"], &["research"]);
+        let (_, modules) = send_request(
+            &router,
+            http::Method::GET,
+            "/ontology/modules",
+            &research,
+            Value::Null,
+        )
+        .await;
+        assert_eq!(modules.as_array().unwrap().len(), 2);
+        let (status, _) = send_request(
+            &router,
+            http::Method::POST,
+            "/ontology/concepts/search",
+            &research,
+            search,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_request(
+            &router,
+            http::Method::PUT,
+            &reorder_uri,
+            &research,
+            reorder.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let lab_editor = group_token("alice", &["mdr-editor"], &["lab"]);
+        let (status, _) = send_request(
+            &router,
+            http::Method::PUT,
+            &reorder_uri,
+            &lab_editor,
+            reorder,
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        // unrestricted again
+        let (status, _) =
+            send_request(&router, http::Method::PUT, &acl_uri, &admin, json!([])).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, modules) = send_request(
+            &router,
+            http::Method::GET,
+            "/ontology/modules",
+            &other,
+            Value::Null,
+        )
+        .await;
+        assert_eq!(modules.as_array().unwrap().len(), 2);
+    }
+
+    async fn send_request(
+        router: &Router,
+        method: http::Method,
+        uri: &str,
+        authorization: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(http::header::AUTHORIZATION, authorization)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
 }
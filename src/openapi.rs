@@ -0,0 +1,209 @@
+use crate::error::{Problem, PROBLEM_JSON};
+use crate::server::ApiContext;
+use crate::{api_key, change_set, code_system, concept, lint, module, terminology};
+use axum::Router;
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, OpenApi as Spec, Ref, RefOr, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "DIZ Marburg MDR Web API"),
+    tags(
+        (name = "modules", description = "CDS modules and their access control"),
+        (name = "concepts", description = "Concepts and ontology trees of the modules"),
+        (name = "change-sets", description = "Reviewed concept drafts which are published together"),
+        (name = "code-systems", description = "Registry of the code systems used by codings"),
+        (name = "terminologies", description = "Codes of the imported terminology releases"),
+        (name = "api-keys", description = "API keys of machine clients")
+    ),
+    components(schemas(Problem)),
+    security(("bearer" = []), ("api_key" = [])),
+    modifiers(&Security)
+)]
+struct ApiDoc;
+
+/// Bearer tokens of the identity provider and `Authorization: ApiKey <key>` headers
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut Spec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "`ApiKey <key>`",
+            ))),
+        );
+    }
+}
+
+/// Error responses of all operations, see [`Problem`]. Applied after merging the routes.
+struct Problems;
+
+impl Modify for Problems {
+    fn modify(&self, openapi: &mut Spec) {
+        let response = |description: &str| {
+            RefOr::T(
+                ResponseBuilder::new()
+                    .description(description)
+                    .content(
+                        PROBLEM_JSON,
+                        ContentBuilder::new()
+                            .schema(Some(RefOr::Ref(Ref::from_schema_name("Problem"))))
+                            .build(),
+                    )
+                    .build(),
+            )
+        };
+        for item in openapi.paths.paths.values_mut() {
+            for operation in [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let responses = &mut operation.responses.responses;
+                responses.insert("4XX".to_owned(), response("Client error"));
+                responses.insert("5XX".to_owned(), response("Server error"));
+            }
+        }
+    }
+}
+
+/// OpenAPI document of the API routes
+pub(crate) fn spec() -> Spec {
+    let (_, mut spec) = OpenApiRouter::<Arc<ApiContext>>::with_openapi(ApiDoc::openapi())
+        .merge(module::routes())
+        .merge(concept::routes())
+        .merge(lint::routes())
+        .merge(change_set::routes())
+        .merge(code_system::routes())
+        .merge(terminology::routes())
+        .merge(api_key::routes())
+        .split_for_parts();
+    Problems.modify(&mut spec);
+    spec
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    SwaggerUi::new("/swagger-ui")
+        .url("/openapi.json", spec())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::PUT,
+        Method::POST,
+        Method::DELETE,
+        Method::PATCH,
+    ];
+
+    /// Status and whether the request was routed, unrouted requests have empty bodies
+    async fn probe(router: &Router, method: Method, uri: &str) -> (StatusCode, bool) {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let routed = !(status == StatusCode::METHOD_NOT_ALLOWED
+            || (status == StatusCode::NOT_FOUND && body.is_empty()));
+        (status, routed)
+    }
+
+    #[sqlx::test]
+    async fn spec_matches_routes_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = module::router()
+            .merge(concept::router())
+            .merge(lint::router())
+            .merge(change_set::router())
+            .merge(code_system::router())
+            .merge(terminology::router())
+            .merge(api_key::router())
+            .merge(router())
+            .with_state(state);
+
+        let (status, _) = probe(&router, Method::GET, "/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let spec: Value = serde_json::to_value(spec()).unwrap();
+        assert_eq!(spec["openapi"], "3.1.0");
+        assert_eq!(
+            spec["paths"]["/ontology/modules"]["get"]["responses"]["4XX"]["content"][PROBLEM_JSON]
+                ["schema"]["$ref"],
+            "#/components/schemas/Problem"
+        );
+
+        let paths = spec["paths"].as_object().unwrap();
+        for path in [
+            "/ontology/modules",
+            "/ontology/tree/{module_id}",
+            "/ontology/modules/{id}/lint",
+            "/ontology/change-sets/{id}/approve",
+            "/ontology/code-systems/{id}",
+            "/ontology/terminology/codes",
+            "/admin/api-keys",
+        ] {
+            assert!(paths.contains_key(path), "{path} is not documented");
+        }
+        for (path, item) in paths {
+            // path parameters are ids
+            let uri = path
+                .split('/')
+                .map(|s| {
+                    if s.starts_with('{') {
+                        "4bfd4e2ecaf5f7ae3ef8400ab0858ec7"
+                    } else {
+                        s
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in METHODS {
+                let documented = item.get(method.as_str().to_lowercase()).is_some();
+                let (status, routed) = probe(&router, method.clone(), &uri).await;
+                assert_eq!(
+                    documented, routed,
+                    "{method} {path}: documented {documented}, status {status}"
+                );
+            }
+        }
+    }
+}
@@ -1,72 +1,199 @@
-use crate::config::AppConfig;
-use crate::{concept, module};
-use axum::{routing::get, Router};
+use crate::auth::Authenticator;
+use crate::config::{AppConfig, Database, Server};
+use crate::metrics::{self, Metrics};
+use crate::shutdown::{self, Requests};
+use crate::telemetry::{AccessLog, REQUEST_ID};
+use crate::tls::TlsListener;
+use crate::tree_cache::{self, TreeCache};
+use crate::{
+    api_key, change_set, code_system, concept, cors, error, health, lint, module, openapi,
+    telemetry, terminology,
+};
+use anyhow::bail;
+use axum::serve::Listener;
+use axum::{middleware, routing::get, Router};
+use sqlx::migrate::Migrator;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
 use std::sync::Arc;
-use std::time::Duration;
-use tower_http::cors::CorsLayer;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, UnixListener};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::EnvFilter;
+
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!();
 
 #[derive(Clone)]
 pub(crate) struct ApiContext {
     pub(crate) db: PgPool,
+    pub(crate) config: AppConfig,
+    /// bearer token validation, `None` if authentication is disabled
+    pub(crate) auth: Option<Arc<Authenticator>>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// built trees, only used while listening for module changes
+    pub(crate) trees: Arc<TreeCache>,
+}
+
+impl ApiContext {
+    pub(crate) fn new(db: PgPool, config: AppConfig) -> anyhow::Result<Self> {
+        let auth = Authenticator::new(&config.auth)?.map(Arc::new);
+        let trees = Arc::new(TreeCache::new(config.cache.trees));
+        Ok(ApiContext {
+            db,
+            config,
+            auth,
+            metrics: Arc::default(),
+            trees,
+        })
+    }
 }
 
 pub async fn serve(config: AppConfig) -> anyhow::Result<()> {
-    let filter = format!(
-        "{}={level},tower_http={level}",
-        env!("CARGO_CRATE_NAME"),
-        level = config.app.log_level
-    );
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into()))
-        // .with_span_events(FmtSpan::CLOSE)
-        .init();
+    let tracer_provider = telemetry::init(&config.app, &config.telemetry)?;
+
+    let pool = connect(&config.database).await;
+    let state = Arc::new(ApiContext::new(pool.clone(), config.clone())?);
+    let trees = state.trees.clone();
+    let requests = Arc::new(Requests::default());
+    let router = api_router(state)?.layer(middleware::from_fn_with_state(
+        requests.clone(),
+        shutdown::track,
+    ));
+
+    MIGRATOR.run(&pool).await?;
+    // the notifying triggers are migrated
+    if config.cache.enabled {
+        tokio::spawn(tree_cache::listen(pool.clone(), trees));
+    }
+
+    let started = Instant::now();
+    let result = listen(&config.server, router, &requests).await;
+
+    match &result {
+        Ok(0) => pool.close().await,
+        // aborted requests keep their connections, their transactions are rolled back on exit
+        _ => {
+            let _ = tokio::time::timeout(Duration::from_secs(1), pool.close()).await;
+        }
+    }
+    match &result {
+        Ok(aborted) => tracing::info!(
+            "Shut down after {}s: {} requests served, {} aborted",
+            started.elapsed().as_secs(),
+            requests.served(),
+            aborted
+        ),
+        Err(e) => tracing::error!("Server failed: {:#}", e),
+    }
+    if let Some(provider) = tracer_provider {
+        // exports pending spans, blocking
+        let shutdown = tokio::task::spawn_blocking(move || provider.shutdown()).await?;
+        if let Err(e) = shutdown {
+            tracing::warn!("Unable to export pending spans: {}", e);
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Serves on the configured listener until shutdown, returns the aborted requests.
+async fn listen(server: &Server, router: Router, requests: &Requests) -> anyhow::Result<u64> {
+    let timeout = Duration::from_secs(server.shutdown_timeout);
 
+    if let Some(path) = &server.unix_socket {
+        if server.tls.is_some() {
+            bail!("server.tls is not supported with server.unix_socket");
+        }
+        // stale socket of a previous run
+        if fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        tracing::info!("listening on {}", path);
+        let aborted =
+            shutdown::serve(listener, router, shutdown::signal(), timeout, requests).await;
+        fs::remove_file(path)?;
+        return aborted;
+    }
+
+    let listener = TcpListener::bind((server.host.as_str(), server.port)).await?;
+    match &server.tls {
+        Some(tls) => {
+            let listener = TlsListener::new(listener, tls)?;
+            tracing::info!("listening on https://{}", listener.local_addr()?);
+            shutdown::serve(listener, router, shutdown::signal(), timeout, requests).await
+        }
+        None => {
+            tracing::info!("listening on http://{}", listener.local_addr()?);
+            shutdown::serve(listener, router, shutdown::signal(), timeout, requests).await
+        }
+    }
+}
+
+pub(crate) async fn connect(config: &Database) -> PgPool {
     let mut db_opts = PgPoolOptions::new();
     // max connections
-    if let Some(max) = config.database.max_connections {
+    if let Some(max) = config.max_connections {
         db_opts = db_opts.max_connections(max);
     }
     // timeout
-    if let Some(timeout) = config.database.timeout {
+    if let Some(timeout) = config.timeout {
         db_opts = db_opts.acquire_timeout(Duration::from_secs(timeout));
     }
 
-    let pool = db_opts
-        .clone()
-        .connect(&config.database.url)
+    db_opts
+        .connect(&config.url)
         .await
-        .expect("Could not connect to database url");
-    let state = Arc::new(ApiContext { db: pool.clone() });
-    let router = api_router(state);
-
-    sqlx::migrate!().run(&pool).await?;
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    tracing::debug!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, router).await.map_err(|e| e.into())
+        .expect("Could not connect to database url")
 }
 
 async fn root() -> &'static str {
     "DIZ Marburg MDR Web API"
 }
 
-fn api_router(state: Arc<ApiContext>) -> Router {
-    Router::new()
+fn api_router(state: Arc<ApiContext>) -> anyhow::Result<Router> {
+    let cors = cors::layer(&state.config.cors)?;
+    let access_log = state.config.app.access_log;
+
+    Ok(Router::new()
         .route("/", get(root))
         .merge(module::router())
         .merge(concept::router())
+        .merge(code_system::router())
+        .merge(lint::router())
+        .merge(terminology::router())
+        .merge(change_set::router())
+        .merge(api_key::router())
+        .merge(health::router())
+        .merge(metrics::router())
+        .merge(openapi::router())
+        // after routing, to record the matched route
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .route_layer(middleware::from_fn(telemetry::record_route))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            api_key::authenticate,
+        ))
         .with_state(state)
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
+        .layer(middleware::from_fn(error::problems))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span)
+                .on_response(AccessLog(access_log)),
+        )
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID))
+        .layer(SetRequestIdLayer::new(REQUEST_ID, MakeRequestUuid))
+        .layer(cors))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::tests::{auth_config, token};
     use axum::body::Body;
     use axum::http::{self, Request, StatusCode};
     use http_body_util::BodyExt;
@@ -75,8 +202,8 @@ mod tests {
 
     #[sqlx::test]
     async fn root_test(pool: PgPool) {
-        let state = Arc::new(ApiContext { db: pool });
-        let router = api_router(state);
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = api_router(state).unwrap();
 
         let response = router
             .oneshot(
@@ -96,4 +223,89 @@ mod tests {
 
         assert_eq!(resp_body, "DIZ Marburg MDR Web API");
     }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn authorization_test(pool: PgPool) {
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        let router = api_router(state).unwrap();
+
+        let module = r#"{"id": "6c2f3ee1-8e5b-4a57-a6b5-70a1f2d9a0d1", "name": "Test",
+            "fdpg_cds_code": "Test", "fdpg_cds_system": "fdpg.mii.cds", "fdpg_cds_version": "1.0.0",
+            "version": "1.0.0"}"#;
+        let tree = "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7";
+        let reorder = r#"{"parent_id": "ce3e2ac86da74b367e7d57a628022aca",
+            "children": ["a52b18659011fe8adeb112ce01327a2d", "6f12427c7db35328e268206113ac1c69"]}"#;
+        let reorder_uri = "/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7/order";
+
+        for (method, uri, body, token, status) in [
+            // anonymous
+            (http::Method::GET, "/", "", None, StatusCode::OK),
+            (http::Method::GET, tree, "", None, StatusCode::UNAUTHORIZED),
+            (
+                http::Method::GET,
+                tree,
+                "",
+                Some(token("carol", &[])),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                http::Method::GET,
+                tree,
+                "",
+                Some(token("carol", &["mdr-reader"])),
+                StatusCode::OK,
+            ),
+            (
+                http::Method::PUT,
+                reorder_uri,
+                reorder,
+                Some(token("carol", &["mdr-reader"])),
+                StatusCode::FORBIDDEN,
+            ),
+            // editors need a change set
+            (
+                http::Method::PUT,
+                reorder_uri,
+                reorder,
+                Some(token("alice", &["mdr-editor"])),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                http::Method::PUT,
+                reorder_uri,
+                reorder,
+                Some(token("root", &["mdr-admin"])),
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                http::Method::POST,
+                "/ontology/modules",
+                module,
+                Some(token("alice", &["mdr-editor"])),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                http::Method::POST,
+                "/ontology/modules",
+                module,
+                Some(token("root", &["mdr-admin"])),
+                StatusCode::CREATED,
+            ),
+        ] {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+            if let Some(token) = token {
+                request = request.header(http::header::AUTHORIZATION, token);
+            }
+            let response = router
+                .clone()
+                .oneshot(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status, "{} {}", uri, body);
+        }
+    }
 }
@@ -0,0 +1,202 @@
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::serve::Listener;
+use axum::Router;
+use std::fmt::Debug;
+use std::future::{Future, IntoFuture};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{self, SignalKind};
+use tokio::sync::Notify;
+
+/// Requests served and in flight, for draining on shutdown
+#[derive(Default, Debug)]
+pub(crate) struct Requests {
+    served: AtomicU64,
+    in_flight: AtomicU64,
+}
+
+impl Requests {
+    pub(crate) fn served(&self) -> u64 {
+        self.served.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in-flight requests, also if the request is cancelled
+struct InFlight<'a>(&'a Requests);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware counting requests
+pub(crate) async fn track(
+    State(requests): State<Arc<Requests>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    requests.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(&requests);
+
+    let response = next.run(request).await;
+    requests.served.fetch_add(1, Ordering::Relaxed);
+    response
+}
+
+/// Completes on SIGTERM (e.g. by Kubernetes) or SIGINT.
+pub(crate) async fn signal() {
+    let mut terminate =
+        unix::signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate.recv() => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Serves until `shutdown` completes, then stops accepting connections and drains
+/// in-flight requests for at most `timeout`. Returns the number of aborted requests.
+pub(crate) async fn serve<L>(
+    listener: L,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    timeout: Duration,
+    requests: &Requests,
+) -> anyhow::Result<u64>
+where
+    L: Listener,
+    L::Addr: Debug,
+{
+    let draining = Arc::new(Notify::new());
+    let signal = {
+        let draining = draining.clone();
+        async move {
+            shutdown.await;
+            draining.notify_one();
+        }
+    };
+    let server = axum::serve(listener, router)
+        .with_graceful_shutdown(signal)
+        .into_future();
+
+    tokio::select! {
+        result = server => {
+            result?;
+            Ok(0)
+        }
+        _ = async {
+            draining.notified().await;
+            if requests.in_flight() > 0 {
+                tracing::info!("Draining {} in-flight requests", requests.in_flight());
+            }
+            tokio::time::sleep(timeout).await;
+        } => {
+            let aborted = requests.in_flight();
+            tracing::warn!(
+                "Shutdown timeout of {}s exceeded, aborting {} in-flight requests",
+                timeout.as_secs(),
+                aborted
+            );
+            Ok(aborted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    /// Starts a server with a handler taking `delay`, returns its address, a shutdown
+    /// trigger and the aborted requests.
+    async fn start(
+        delay: Duration,
+        timeout: Duration,
+        requests: Arc<Requests>,
+    ) -> (String, oneshot::Sender<()>, JoinHandle<u64>) {
+        let router = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    tokio::time::sleep(delay).await;
+                    "imported"
+                }),
+            )
+            .layer(from_fn_with_state(requests.clone(), track));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = oneshot::channel();
+
+        let server = tokio::spawn(async move {
+            let shutdown = async {
+                rx.await.ok();
+            };
+            serve(listener, router, shutdown, timeout, &requests)
+                .await
+                .unwrap()
+        });
+        (addr, tx, server)
+    }
+
+    async fn request(addr: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok();
+        response
+    }
+
+    #[tokio::test]
+    async fn drains_in_flight_requests() {
+        let requests = Arc::new(Requests::default());
+        let (addr, shutdown, server) = start(
+            Duration::from_millis(300),
+            Duration::from_secs(10),
+            requests.clone(),
+        )
+        .await;
+
+        let response = tokio::spawn(async move { request(&addr).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(requests.in_flight(), 1);
+        shutdown.send(()).unwrap();
+
+        assert!(response.await.unwrap().ends_with("imported"));
+        assert_eq!(server.await.unwrap(), 0);
+        assert_eq!(requests.served(), 1);
+        assert_eq!(requests.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn aborts_after_timeout() {
+        let requests = Arc::new(Requests::default());
+        let (addr, shutdown, server) = start(
+            Duration::from_secs(60),
+            Duration::from_millis(100),
+            requests.clone(),
+        )
+        .await;
+
+        tokio::spawn(async move { request(&addr).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        assert_eq!(server.await.unwrap(), 1);
+        assert_eq!(requests.served(), 0);
+    }
+}
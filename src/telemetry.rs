@@ -0,0 +1,452 @@
+use crate::config::{App, LogFormat, Telemetry};
+use axum::extract::{MatchedPath, Request};
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{
+    Span as _, SpanContext, SpanKind, TraceContextExt, TraceFlags, TraceState, Tracer as _,
+    TracerProvider as _,
+};
+use opentelemetry::{Context as OtelContext, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::time::{Duration, SystemTime};
+use tower_http::trace::OnResponse;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Metadata, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetrySpanExt, OtelData};
+use tracing_subscriber::filter::{filter_fn, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Header of the request id, generated unless sent by the client
+pub(crate) const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Target of the access log, logged regardless of `app.log_level`
+const ACCESS_LOG: &str = "access_log";
+
+/// Initializes logging and, if enabled, the export of traces. The returned provider has to
+/// be shut down to export pending spans.
+pub(crate) fn init(app: &App, telemetry: &Telemetry) -> anyhow::Result<Option<SdkTracerProvider>> {
+    let provider = if telemetry.enabled {
+        Some(provider(telemetry)?)
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(log_layer(app, std::io::stdout))
+        .with(provider.as_ref().map(layer))
+        .init();
+
+    Ok(provider)
+}
+
+/// Logs in the configured format. Events within a request include the fields of the
+/// request span, e.g. its `request_id`.
+fn log_layer<W>(app: &App, writer: W) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = format!(
+        "{}={level},tower_http={level},{ACCESS_LOG}=info",
+        env!("CARGO_CRATE_NAME"),
+        level = app.log_level
+    );
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into());
+
+    match app.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+fn provider(telemetry: &Telemetry) -> anyhow::Result<SdkTracerProvider> {
+    let mut exporter = SpanExporter::builder().with_http();
+    if let Some(endpoint) = &telemetry.endpoint {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter.build()?)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(telemetry.service.clone())
+                .build(),
+        )
+        .build())
+}
+
+/// Exports the spans of this crate and the database queries within them.
+fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    let spans = tracing_opentelemetry::layer()
+        .with_tracer(tracer.clone())
+        .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO));
+    let queries = QueryLayer { tracer }.with_filter(filter_fn(|m: &Metadata| {
+        m.target() == QUERY_TARGET
+            || (m.is_span()
+                && m.target().starts_with(env!("CARGO_CRATE_NAME"))
+                && *m.level() <= Level::INFO)
+    }));
+
+    spans.and_then(queries)
+}
+
+/// Target of the events sqlx logs after each query
+const QUERY_TARGET: &str = "sqlx::query";
+
+/// Creates client spans from the query events of sqlx, as children of the current span.
+struct QueryLayer {
+    tracer: SdkTracer,
+}
+
+impl<S> Layer<S> for QueryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != QUERY_TARGET {
+            return;
+        }
+        // the dispatcher is not available here, the parent is taken from the span's data
+        let Some(parent) = ctx.lookup_current().and_then(|span| {
+            let extensions = span.extensions();
+            let data = extensions.get::<OtelData>()?;
+            Some(SpanContext::new(
+                data.trace_id()?,
+                data.span_id()?,
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ))
+        }) else {
+            return;
+        };
+
+        let mut query = Query::default();
+        event.record(&mut query);
+        let end = SystemTime::now();
+        let start = end
+            .checked_sub(Duration::from_secs_f64(query.elapsed_secs))
+            .unwrap_or(end);
+        let operation = query
+            .statement
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase();
+
+        let mut attributes = vec![
+            KeyValue::new("db.system.name", "postgresql"),
+            KeyValue::new("db.operation.name", operation.clone()),
+            KeyValue::new("db.query.text", query.statement),
+        ];
+        if let Some(rows) = query.rows_returned {
+            attributes.push(KeyValue::new("db.response.returned_rows", rows as i64));
+        }
+        let mut span = self
+            .tracer
+            .span_builder(operation)
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes(attributes)
+            .start_with_context(
+                &self.tracer,
+                &OtelContext::new().with_remote_span_context(parent),
+            );
+        span.end_with_timestamp(end);
+    }
+}
+
+/// Fields of a sqlx query event
+#[derive(Default)]
+struct Query {
+    statement: String,
+    rows_returned: Option<u64>,
+    elapsed_secs: f64,
+}
+
+impl Visit for Query {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = value;
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "rows_returned" {
+            self.rows_returned = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "db.statement" {
+            self.statement = value.trim().to_owned();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Span of a request, continuing the trace of a W3C `traceparent` header
+pub(crate) fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+        caller = tracing::field::Empty,
+        otel.name = %request.method(),
+        otel.kind = "server",
+        http.route = tracing::field::Empty,
+    );
+    let parent = TraceContextPropagator::new().extract(&Headers(request.headers()));
+    // fails if traces are not exported
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+/// Logs responses with their status and duration, to the access log if enabled
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AccessLog(pub(crate) bool);
+
+impl<B> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &axum::http::Response<B>, latency: Duration, _: &Span) {
+        let status = response.status().as_u16();
+        let duration_ms = latency.as_millis() as u64;
+        if self.0 {
+            tracing::info!(target: ACCESS_LOG, status, duration_ms, "request completed");
+        } else {
+            tracing::debug!(status, duration_ms, "finished processing request");
+        }
+    }
+}
+
+/// Middleware naming the request span by its route, e.g. `GET /ontology/tree/{module_id}`
+pub(crate) async fn record_route(path: MatchedPath, request: Request, next: Next) -> Response {
+    let span = Span::current();
+    span.record("http.route", path.as_str());
+    // the span is already started, it is renamed directly
+    span.context()
+        .span()
+        .update_name(format!("{} {}", request.method(), path.as_str()));
+
+    next.run(request).await
+}
+
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::{auth_config, token};
+    use crate::concept;
+    use crate::config::AppConfig;
+    use crate::server::ApiContext;
+    use axum::body::Body;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::StatusCode;
+    use axum::middleware;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+    use tower_http::trace::TraceLayer;
+
+    /// Log output of a test
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        /// Logged lines of the access log
+        fn access_log(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str::<Value>(l).unwrap())
+                .filter(|l| l["target"] == ACCESS_LOG)
+                .collect()
+        }
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn logs_request_ids_test(pool: PgPool) {
+        let app = App {
+            log_level: "info".to_owned(),
+            log_format: LogFormat::Json,
+            access_log: true,
+        };
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let _guard = tracing_subscriber::registry()
+            .with(log_layer(&app, move || writer.clone()))
+            .set_default();
+
+        let state = Arc::new(ApiContext::new(pool, auth_config()).unwrap());
+        let router = concept::router()
+            .with_state(state)
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(request_span)
+                    .on_response(AccessLog(true)),
+            )
+            .layer(PropagateRequestIdLayer::new(REQUEST_ID))
+            .layer(SetRequestIdLayer::new(REQUEST_ID, MakeRequestUuid));
+
+        // propagated
+        let response = router
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7")
+                    .header(REQUEST_ID, "portal-42")
+                    .header(AUTHORIZATION, token("alice", &["mdr-reader"]))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[REQUEST_ID], "portal-42");
+        drop(response);
+
+        // generated
+        let response = router
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let generated = response.headers()[REQUEST_ID].to_str().unwrap().to_owned();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+        drop(response);
+
+        let log = buffer.access_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0]["fields"]["status"], 200);
+        assert!(log[0]["fields"]["duration_ms"].is_u64());
+        assert_eq!(log[0]["span"]["request_id"], "portal-42");
+        assert_eq!(log[0]["span"]["caller"], "alice");
+        assert_eq!(log[1]["fields"]["status"], 401);
+        assert_eq!(log[1]["span"]["request_id"], generated.as_str());
+        assert_eq!(log[1]["span"].get("caller"), None);
+    }
+
+    #[sqlx::test(fixtures("concepts"))]
+    async fn exports_spans_test(pool: PgPool) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let _guard = tracing_subscriber::registry()
+            .with(layer(&provider))
+            .set_default();
+
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = concept::router()
+            .route_layer(middleware::from_fn(record_route))
+            .with_state(state)
+            .layer(TraceLayer::new_for_http().make_span_with(request_span));
+        let response = router
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/ontology/tree/4bfd4e2ecaf5f7ae3ef8400ab0858ec7")
+                    .header(
+                        "traceparent",
+                        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // ends the request span
+        drop(response);
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert!(spans.iter().all(|s| s.span_context.trace_id() == trace_id));
+
+        let request = spans
+            .iter()
+            .find(|s| s.name == "GET /ontology/tree/{module_id}")
+            .unwrap_or_else(|| panic!("no request span: {:?}", spans));
+        assert_eq!(
+            request.parent_span_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+        let request_id = request.span_context.span_id();
+
+        let tree = spans
+            .iter()
+            .find(|s| s.name == "build_concept_tree")
+            .expect("tree span");
+        assert_eq!(tree.parent_span_id, request_id);
+
+        let query = spans
+            .iter()
+            .find(|s| {
+                s.name == "SELECT"
+                    && s.span_kind == SpanKind::Client
+                    && s.attributes.iter().any(|a| {
+                        a.key.as_str() == "db.query.text"
+                            && a.value.as_str().contains("concepts_with_codings")
+                    })
+            })
+            .expect("query span");
+        assert_eq!(query.parent_span_id, request_id);
+    }
+}
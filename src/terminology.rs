@@ -0,0 +1,613 @@
+use crate::auth::{Role, User};
+use crate::coding::Coding;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::server::{self, ApiContext};
+use anyhow::{anyhow, Context};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::{debug_handler, Json, Router};
+use clap::ValueEnum;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// Release file formats of external terminologies
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum Format {
+    /// LOINC table (Loinc.csv)
+    Loinc,
+    /// ICD-10-GM ClaML (XML)
+    Icd10gm,
+    /// OPS ClaML (XML)
+    Ops,
+    /// ATC text file, code and display separated by tab or semicolon
+    Atc,
+}
+
+impl Format {
+    fn system(self) -> &'static str {
+        match self {
+            Format::Loinc => "http://loinc.org",
+            Format::Icd10gm => "http://fhir.de/CodeSystem/bfarm/icd-10-gm",
+            Format::Ops => "http://fhir.de/CodeSystem/bfarm/ops",
+            Format::Atc => "http://fhir.de/CodeSystem/bfarm/atc",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Loinc => "loinc",
+            Format::Icd10gm => "icd10gm",
+            Format::Ops => "ops",
+            Format::Atc => "atc",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, FromRow, ToSchema, Debug, PartialEq, Clone)]
+pub(crate) struct TerminologyCode {
+    system: String,
+    version: String,
+    code: String,
+    display: String,
+    status: String,
+}
+
+/// Code parsed from a release file
+#[derive(Debug, PartialEq)]
+struct Entry {
+    code: String,
+    display: String,
+    status: String,
+}
+
+const ACTIVE: &str = "active";
+/// statuses of codes which should not be used anymore
+const INACTIVE: [&str; 2] = ["deprecated", "discouraged"];
+
+#[derive(Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CodeSearch {
+    /// canonical url of the code system
+    system: String,
+    /// start of the code or part of the display, at least 2 characters
+    q: String,
+    /// all imported versions if not given
+    version: Option<String>,
+    /// 20 by default, at most 100
+    limit: Option<i64>,
+}
+
+pub(crate) fn router() -> Router<Arc<ApiContext>> {
+    routes().into()
+}
+
+/// Routes with their OpenAPI operations
+pub(crate) fn routes() -> OpenApiRouter<Arc<ApiContext>> {
+    OpenApiRouter::new().routes(routes!(search))
+}
+
+/// Searches the imported codes of a terminology
+#[utoipa::path(
+    get,
+    path = "/ontology/terminology/codes",
+    tag = "terminologies",
+    params(CodeSearch),
+    responses((status = 200, description = "Matching codes", body = Vec<TerminologyCode>))
+)]
+#[debug_handler]
+async fn search(
+    State(ctx): State<Arc<ApiContext>>,
+    user: User,
+    Query(search): Query<CodeSearch>,
+) -> Result<Json<Vec<TerminologyCode>>, ApiError> {
+    user.require(Role::Reader)?;
+
+    if search.q.len() < 2 {
+        return Err(ApiError(
+            anyhow!("Search term must consist of at least 2 characters"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let q = search.q.to_lowercase();
+    let result = sqlx::query_as!(
+        TerminologyCode,
+        r#"select system, version, code, display, status
+           from terminology_codes
+           where system = $1
+           and ($2::text is null or version = $2)
+           and (lower(code) like $4 || '%' escape '\'
+                or lower(display) like '%' || $4 || '%' escape '\')
+           order by lower(code) = $3 desc, lower(code) like $4 || '%' escape '\' desc,
+                    status = 'active' desc, code, version desc
+           limit $5"#,
+        search.system,
+        search.version,
+        q,
+        escape_like(&q),
+        search.limit.unwrap_or(20).clamp(1, 100)
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(result))
+}
+
+/// Escapes the wildcards of a `like` pattern, the search term is matched literally
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Imports a terminology release file, replacing a previous import of the same version.
+/// Returns the number of codes imported.
+pub(crate) async fn run_import(
+    config: AppConfig,
+    format: Format,
+    file: &Path,
+    version: &str,
+    system: Option<String>,
+) -> anyhow::Result<usize> {
+    let entries = parse_file(format, file)?;
+    let system = system.unwrap_or(format.system().to_owned());
+
+    let pool = server::connect(&config.database).await;
+    import(&pool, format, &system, version, entries).await
+}
+
+fn parse_file(format: Format, file: &Path) -> anyhow::Result<Vec<Entry>> {
+    let reader = BufReader::new(
+        File::open(file).with_context(|| format!("Unable to open {}", file.display()))?,
+    );
+
+    match format {
+        Format::Loinc => parse_loinc(reader),
+        Format::Icd10gm | Format::Ops => parse_claml(reader),
+        Format::Atc => parse_atc(reader),
+    }
+}
+
+async fn import(
+    db: &PgPool,
+    format: Format,
+    system: &str,
+    version: &str,
+    entries: Vec<Entry>,
+) -> anyhow::Result<usize> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"delete from terminologies where system = $1 and version = $2"#,
+        system,
+        version
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"insert into terminologies (system, version, format) values ($1, $2, $3)"#,
+        system,
+        version,
+        format.name()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for chunk in entries.chunks(5000) {
+        let codes: Vec<&str> = chunk.iter().map(|e| e.code.as_str()).collect();
+        let displays: Vec<&str> = chunk.iter().map(|e| e.display.as_str()).collect();
+        let statuses: Vec<&str> = chunk.iter().map(|e| e.status.as_str()).collect();
+
+        sqlx::query!(
+            r#"insert into terminology_codes (system, version, code, display, status)
+               select $1, $2, code, display, status
+               from unnest($3::text[], $4::text[], $5::text[]) e(code, display, status)
+               on conflict do nothing"#,
+            system,
+            version,
+            &codes as &[&str],
+            &displays as &[&str],
+            &statuses as &[&str]
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // make the version known to the code system registry
+    sqlx::query!(
+        r#"update code_systems set versions = array_append(versions, $2)
+           where url = $1 and not $2 = any(versions)"#,
+        system,
+        version
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(entries.len())
+}
+
+/// Checks codings against imported terminologies and returns the problems found: unknown
+/// or deprecated codes and displays differing from the terminology. Codings of code systems
+/// (or versions) which have not been imported are skipped.
+pub(crate) async fn validate(
+    conn: &mut PgConnection,
+    codings: &[&Coding],
+) -> Result<Vec<String>, sqlx::Error> {
+    let systems: Vec<String> = codings.iter().map(|c| c.system.clone()).collect();
+    let loaded: HashSet<(String, String)> = sqlx::query!(
+        r#"select system, version from terminologies where system = any($1)"#,
+        &systems
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|t| (t.system, t.version))
+    .collect();
+
+    let checked: Vec<&&Coding> = codings
+        .iter()
+        .filter(|c| match &c.version {
+            Some(version) => loaded.contains(&(c.system.clone(), version.clone())),
+            None => loaded.iter().any(|(system, _)| *system == c.system),
+        })
+        .collect();
+    if checked.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let systems: Vec<String> = checked.iter().map(|c| c.system.clone()).collect();
+    let codes: Vec<String> = checked.iter().map(|c| c.code.clone()).collect();
+    // unversioned codings are checked against the latest import
+    let mut found: HashMap<(String, String), TerminologyCode> = HashMap::new();
+    for t in sqlx::query_as!(
+        TerminologyCode,
+        r#"select t.system, t.version, t.code, t.display, t.status
+           from unnest($1::text[], $2::text[]) l(system, code)
+           join terminology_codes t on t.system = l.system and t.code = l.code
+           join terminologies r on r.system = t.system and r.version = t.version
+           order by r.imported_at"#,
+        &systems,
+        &codes
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        found.insert((t.system.clone(), t.code.clone()), t.clone());
+        found.insert((t.system.clone(), format!("{}|{}", t.code, t.version)), t);
+    }
+
+    let mut problems = vec![];
+    for c in checked {
+        let key = match &c.version {
+            Some(version) => (c.system.clone(), format!("{}|{}", c.code, version)),
+            None => (c.system.clone(), c.code.clone()),
+        };
+        let Some(t) = found.get(&key) else {
+            problems.push(match &c.version {
+                Some(version) => format!("Unknown code {} in {} {}", c.code, c.system, version),
+                None => format!("Unknown code {} in {}", c.code, c.system),
+            });
+            continue;
+        };
+        if INACTIVE.contains(&t.status.as_str()) {
+            problems.push(format!(
+                "Code {} in {} {} is {}",
+                c.code, c.system, t.version, t.status
+            ));
+        }
+        if !c.display.is_empty() && c.display != t.display {
+            problems.push(format!(
+                "Display of {} in {} is '{}', expected '{}'",
+                c.code, c.system, c.display, t.display
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+fn parse_loinc(reader: impl Read) -> anyhow::Result<Vec<Entry>> {
+    let mut csv = csv::Reader::from_reader(reader);
+    let headers = csv.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or(anyhow!("Missing LOINC column: {}", name))
+    };
+    let (code, display, status) = (
+        column("LOINC_NUM")?,
+        column("LONG_COMMON_NAME")?,
+        column("STATUS")?,
+    );
+
+    let mut entries = vec![];
+    for record in csv.records() {
+        let record = record?;
+        entries.push(Entry {
+            code: record[code].to_owned(),
+            display: record[display].to_owned(),
+            status: record[status].to_lowercase(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parses the classes of a ClaML file (ICD-10-GM, OPS) with their preferred label.
+fn parse_claml(reader: impl BufRead) -> anyhow::Result<Vec<Entry>> {
+    let mut xml = Reader::from_reader(reader);
+    let mut buf = vec![];
+
+    let mut entries = vec![];
+    let mut code: Option<String> = None;
+    let mut preferred = false;
+    let mut label: Option<String> = None;
+    loop {
+        match xml.read_event_into(&mut buf)? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"Class" => {
+                    code = match e.try_get_attribute("code")? {
+                        Some(a) => Some(a.unescape_value()?.into_owned()),
+                        None => None,
+                    };
+                }
+                b"Rubric" if code.is_some() => {
+                    preferred = e
+                        .try_get_attribute("kind")?
+                        .is_some_and(|a| a.value.as_ref() == b"preferred");
+                }
+                b"Label" if preferred => label = Some(String::new()),
+                _ => {}
+            },
+            Event::Text(t) => {
+                if let Some(l) = label.as_mut() {
+                    l.push_str(&t.unescape()?);
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"Label" if preferred => {
+                    if let (Some(c), Some(l)) = (code.as_ref(), label.take()) {
+                        entries.push(Entry {
+                            code: c.clone(),
+                            display: l.split_whitespace().collect::<Vec<_>>().join(" "),
+                            status: ACTIVE.to_owned(),
+                        });
+                    }
+                    preferred = false;
+                }
+                b"Class" => code = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(entries)
+}
+
+fn parse_atc(reader: impl BufRead) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let Some((code, display)) = line.split_once(['\t', ';']) else {
+            continue;
+        };
+        let code = code.trim().trim_matches('"');
+        // skips headers and empty lines
+        if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        entries.push(Entry {
+            code: code.to_owned(),
+            display: display.trim().trim_matches('"').to_owned(),
+            status: ACTIVE.to_owned(),
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{self, Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn entry(code: &str, display: &str, status: &str) -> Entry {
+        Entry {
+            code: code.to_owned(),
+            display: display.to_owned(),
+            status: status.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_loinc() {
+        let csv = r#""LOINC_NUM","COMPONENT","STATUS","LONG_COMMON_NAME"
+"20578-1","Vancomycin","ACTIVE","Vancomycin [Mass/volume] in Serum or Plasma"
+"1-8","Acyclovir","DEPRECATED","Acyclovir [Susceptibility]"
+"#;
+
+        assert_eq!(
+            parse_loinc(csv.as_bytes()).unwrap(),
+            vec![
+                entry(
+                    "20578-1",
+                    "Vancomycin [Mass/volume] in Serum or Plasma",
+                    "active"
+                ),
+                entry("1-8", "Acyclovir [Susceptibility]", "deprecated"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_claml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ClaML version="2.0.0">
+  <Class code="Q50-Q56" kind="block">
+    <SuperClass code="XVII"/>
+    <Rubric id="1" kind="preferred">
+      <Label xml:lang="de" xml:space="default">Angeborene Fehlbildungen der Genitalorgane</Label>
+    </Rubric>
+  </Class>
+  <Class code="Q50" kind="category">
+    <Rubric id="2" kind="preferred">
+      <Label xml:lang="de">Angeborene Fehlbildungen der Ovarien, der Tubae uterinae
+        und der Ligg. lata uteri</Label>
+    </Rubric>
+    <Rubric id="3" kind="inclusion">
+      <Label xml:lang="de">Zyste &amp; Ovar</Label>
+    </Rubric>
+  </Class>
+  <Class code="Q50.0" kind="category">
+    <Rubric id="4" kind="preferred">
+      <Label xml:lang="de">Angeborenes Fehlen des Ovars <Reference>E28.3</Reference> &amp; mehr</Label>
+    </Rubric>
+  </Class>
+</ClaML>
+"#;
+
+        assert_eq!(
+            parse_claml(xml.as_bytes()).unwrap(),
+            vec![
+                entry(
+                    "Q50-Q56",
+                    "Angeborene Fehlbildungen der Genitalorgane",
+                    "active"
+                ),
+                entry(
+                    "Q50",
+                    "Angeborene Fehlbildungen der Ovarien, der Tubae uterinae und der Ligg. lata uteri",
+                    "active"
+                ),
+                entry(
+                    "Q50.0",
+                    "Angeborenes Fehlen des Ovars E28.3 & mehr",
+                    "active"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_atc() {
+        let txt = "ATC-Code;Bedeutung\nJ01XA01;Vancomycin\n\nJ02AC03\tVoriconazol\n";
+
+        assert_eq!(
+            parse_atc(txt.as_bytes()).unwrap(),
+            vec![
+                entry("J01XA01", "Vancomycin", "active"),
+                entry("J02AC03", "Voriconazol", "active"),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn import_validate_and_search_test(pool: PgPool) {
+        let entries = vec![
+            entry(
+                "20578-1",
+                "Vancomycin [Mass/volume] in Serum or Plasma",
+                "active",
+            ),
+            entry("1-8", "Acyclovir [Susceptibility]", "deprecated"),
+            entry(
+                "20579-9",
+                "Vancomycin [Mass/volume] in Serum or Plasma --peak",
+                "active",
+            ),
+        ];
+        let count = import(&pool, Format::Loinc, "http://loinc.org", "2.73", entries)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+
+        let coding = |code: &str, display: &str, version: Option<&str>| Coding {
+            code: code.to_owned(),
+            system: "http://loinc.org".to_owned(),
+            display: display.to_owned(),
+            version: version.map(|v| v.to_owned()),
+        };
+        let codings = [
+            coding(
+                "20578-1",
+                "Vancomycin [Mass/volume] in Serum or Plasma",
+                Some("2.73"),
+            ),
+            coding("1-8", "Acyclovir [Susceptibility]", None),
+            coding("20579-9", "Vancomycin peak", Some("2.73")),
+            coding("0000-0", "Unknown", Some("2.73")),
+            // version not imported
+            coding("0000-0", "Unknown", Some("2.42")),
+        ];
+        let problems = validate(
+            &mut pool.acquire().await.unwrap(),
+            &codings.iter().collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            problems,
+            vec![
+                "Code 1-8 in http://loinc.org 2.73 is deprecated",
+                "Display of 20579-9 in http://loinc.org is 'Vancomycin peak', \
+                 expected 'Vancomycin [Mass/volume] in Serum or Plasma --peak'",
+                "Unknown code 0000-0 in http://loinc.org 2.73",
+            ]
+        );
+
+        // autocomplete, wildcards match literally
+        let state = Arc::new(ApiContext::new(pool, AppConfig::default()).unwrap());
+        let router = router().with_state(state);
+        for (q, expected) in [
+            ("2057", vec!["20578-1", "20579-9"]),
+            ("20_7", vec![]),
+            ("vanco%peak", vec![]),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::GET)
+                        .uri(format!(
+                            "/ontology/terminology/codes?system=http://loinc.org&q={}",
+                            q.replace('%', "%25")
+                        ))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let codes: Vec<TerminologyCode> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                codes.iter().map(|c| c.code.as_str()).collect::<Vec<_>>(),
+                expected,
+                "{}",
+                q
+            );
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::config::Tls;
+use anyhow::Context as _;
+use axum::serve::Listener;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// TCP listener terminating TLS. The certificate is reloaded on SIGHUP, established
+/// connections keep their certificate.
+pub(crate) struct TlsListener {
+    tcp: TcpListener,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl TlsListener {
+    pub(crate) fn new(tcp: TcpListener, tls: &Tls) -> anyhow::Result<Self> {
+        let config = Arc::new(RwLock::new(Arc::new(load(tls)?)));
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let reloaded = config.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match load(&tls) {
+                    Ok(c) => {
+                        *reloaded.write().unwrap() = Arc::new(c);
+                        tracing::info!("Reloaded TLS certificate {}", tls.cert_file);
+                    }
+                    // keep the previous certificate
+                    Err(e) => tracing::error!("Unable to reload TLS certificate: {:#}", e),
+                }
+            }
+        });
+
+        Ok(TlsListener { tcp, config })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let (io, addr) = Listener::accept(&mut self.tcp).await;
+            let config = self.config.read().unwrap().clone();
+            // the handshake is completed by the first read
+            match ServerConnection::new(config) {
+                Ok(conn) => {
+                    let stream = TlsStream {
+                        io,
+                        conn,
+                        closed: false,
+                    };
+                    return (stream, addr);
+                }
+                Err(e) => tracing::error!("Unable to accept TLS connection: {}", e),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.tcp.local_addr()
+    }
+}
+
+/// Loads the certificate chain and private key.
+pub(crate) fn load(tls: &Tls) -> anyhow::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Unable to read certificate {}", tls.cert_file))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key_file)
+        .with_context(|| format!("Unable to read private key {}", tls.key_file))?;
+
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+/// Server side TLS connection
+pub(crate) struct TlsStream {
+    io: TcpStream,
+    conn: ServerConnection,
+    /// close notification sent
+    closed: bool,
+}
+
+impl TlsStream {
+    /// Reads TLS records from the socket.
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut reader = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        match self.conn.read_tls(&mut reader) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+
+    /// Writes pending TLS records to the socket.
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.conn.wants_write() {
+            let mut writer = SyncIo {
+                io: &mut self.io,
+                cx,
+            };
+            match self.conn.write_tls(&mut writer) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+                Err(e) => return Poll::Ready(Err(e)),
+                Ok(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Ok(_) => {}
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match this.conn.reader().read(buf.initialize_unfilled()) {
+                Ok(n) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            // handshake messages and alerts, the socket may be busy
+            if let Poll::Ready(Err(e)) = this.poll_write_tls(cx) {
+                return Poll::Ready(Err(e));
+            }
+
+            match ready!(this.poll_read_tls(cx))? {
+                // end of stream, the reader reports a missing close notification
+                0 => continue,
+                _ => {
+                    if let Err(e) = this.conn.process_new_packets() {
+                        // send the alert
+                        let _ = this.poll_write_tls(cx);
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut written = 0;
+        while written < buf.len() {
+            let n = this.conn.writer().write(&buf[written..])?;
+            written += n;
+            if n == 0 && !this.conn.wants_write() {
+                // plaintext is buffered until the handshake is completed by a read of the connection
+                if written == 0 {
+                    return Poll::Pending;
+                }
+                break;
+            }
+            match this.poll_write_tls(cx) {
+                // the send buffer is drained, write the rest
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending if written == 0 => return Poll::Pending,
+                Poll::Pending => break,
+            }
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.conn.writer().flush()?;
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closed {
+            this.conn.send_close_notify();
+            this.closed = true;
+        }
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
+
+/// Blocking IO for rustls, mapping pending operations to `WouldBlock`
+struct SyncIo<'a, 'b> {
+    io: &'a mut TcpStream,
+    cx: &'a mut Context<'b>,
+}
+
+impl Read for SyncIo<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut *self.io).poll_read(self.cx, &mut buf) {
+            Poll::Ready(Ok(())) => Ok(buf.filled().len()),
+            Poll::Ready(Err(e)) => Err(e),
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for SyncIo<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::Router;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/tls_cert.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/tls_key.pem");
+
+    /// Sends a request over TLS trusting the test certificate, returns the response.
+    fn get_tls(addr: SocketAddr, path: &str) -> String {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(CERT).unwrap())
+            .unwrap();
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let conn =
+            ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap())
+                .unwrap();
+        let mut stream = StreamOwned::new(conn, std::net::TcpStream::connect(addr).unwrap());
+
+        stream
+            .write_all(
+                format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .unwrap();
+        // let the server fill its buffers
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_tls() {
+        let tls = Tls {
+            cert_file: CERT.to_owned(),
+            key_file: KEY.to_owned(),
+        };
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::new(tcp, &tls).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/", get(|| async { "DIZ Marburg MDR Web API" }))
+            .route("/large", get(|| async { "MDR".repeat(1 << 20) }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let response = tokio::task::spawn_blocking(move || get_tls(addr, "/"))
+            .await
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("DIZ Marburg MDR Web API"));
+
+        // exceeds the send buffer of the connection
+        let response = tokio::task::spawn_blocking(move || get_tls(addr, "/large"))
+            .await
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&"MDR".repeat(1 << 20)));
+    }
+
+    #[test]
+    fn fails_on_invalid_files() {
+        let tls = Tls {
+            cert_file: KEY.to_owned(),
+            key_file: KEY.to_owned(),
+        };
+        assert!(load(&tls).is_err());
+    }
+}
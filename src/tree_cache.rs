@@ -0,0 +1,262 @@
+use axum::body::Bytes;
+use sqlx::postgres::PgListener;
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Channel of the notifications sent by the revision triggers, the payload is the module id
+const CHANNEL: &str = "module_changes";
+/// Delay before reconnecting after the listener failed
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Representation of a tree, by module, display languages and included parts
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct TreeKey {
+    pub(crate) module_id: Uuid,
+    pub(crate) languages: String,
+    pub(crate) include: String,
+}
+
+/// Serialized tree with its entity tag
+#[derive(Debug)]
+pub(crate) struct CachedTree {
+    pub(crate) etag: String,
+    pub(crate) json: Bytes,
+    built: Instant,
+}
+
+/// Version of the cached trees of a module at the start of a build. Trees built from data
+/// changed in the meantime are not cached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Generation {
+    epoch: u64,
+    module: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    trees: HashMap<TreeKey, Arc<CachedTree>>,
+    generations: HashMap<Uuid, u64>,
+    /// bumped if notifications may have been missed
+    epoch: u64,
+}
+
+/// Built trees of the modules, invalidated by notifications of changed modules. Trees are
+/// only cached while listening, other replicas may change modules at any time.
+#[derive(Debug)]
+pub(crate) struct TreeCache {
+    state: Mutex<State>,
+    listening: AtomicBool,
+    max_trees: usize,
+}
+
+impl TreeCache {
+    pub(crate) fn new(max_trees: usize) -> Self {
+        TreeCache {
+            state: Mutex::default(),
+            listening: AtomicBool::new(false),
+            max_trees,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &TreeKey) -> Option<Arc<CachedTree>> {
+        if !self.listening.load(Ordering::Acquire) {
+            return None;
+        }
+        self.state.lock().unwrap().trees.get(key).cloned()
+    }
+
+    pub(crate) fn generation(&self, module_id: Uuid) -> Generation {
+        let state = self.state.lock().unwrap();
+        Generation {
+            epoch: state.epoch,
+            module: state
+                .generations
+                .get(&module_id)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Caches a tree built at the generation, unless its module changed since.
+    /// The oldest tree is evicted if the cache is full.
+    pub(crate) fn insert(&self, key: TreeKey, generation: Generation, etag: String, json: Bytes) {
+        if !self.listening.load(Ordering::Acquire) || self.max_trees == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let current = Generation {
+            epoch: state.epoch,
+            module: state
+                .generations
+                .get(&key.module_id)
+                .copied()
+                .unwrap_or_default(),
+        };
+        if current != generation {
+            return;
+        }
+        if state.trees.len() >= self.max_trees
+            && !state.trees.contains_key(&key)
+            && let Some(oldest) = state
+                .trees
+                .iter()
+                .min_by_key(|(_, t)| t.built)
+                .map(|(k, _)| k.clone())
+        {
+            state.trees.remove(&oldest);
+        }
+        let tree = CachedTree {
+            etag,
+            json,
+            built: Instant::now(),
+        };
+        state.trees.insert(key, Arc::new(tree));
+    }
+
+    fn invalidate(&self, module_id: Uuid) {
+        let mut state = self.state.lock().unwrap();
+        *state.generations.entry(module_id).or_default() += 1;
+        state.trees.retain(|k, _| k.module_id != module_id);
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        state.trees.clear();
+    }
+
+    /// Trees built before listening (again) may miss changes, they are dropped
+    fn set_listening(&self, listening: bool) {
+        self.clear();
+        self.listening.store(listening, Ordering::Release);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().trees.len()
+    }
+}
+
+/// Listens for changed modules and invalidates their trees, until the pool is closed.
+/// The cache is cleared while the connection is lost, notifications may be missed.
+pub(crate) async fn listen(db: PgPool, cache: Arc<TreeCache>) {
+    while !db.is_closed() {
+        let mut listener = match PgListener::connect_with(&db).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::warn!("Unable to listen for module changes: {}", e);
+                tokio::time::sleep(RETRY).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(CHANNEL).await {
+            tracing::warn!("Unable to listen for module changes: {}", e);
+            tokio::time::sleep(RETRY).await;
+            continue;
+        }
+        cache.set_listening(true);
+        tracing::debug!("Listening for module changes");
+
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => match Uuid::parse_str(notification.payload()) {
+                    Ok(module_id) => cache.invalidate(module_id),
+                    Err(_) => cache.clear(),
+                },
+                // reconnected on the next receive
+                Ok(None) => {
+                    tracing::warn!("Lost connection listening for module changes");
+                    cache.set_listening(false);
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("Unable to receive module changes: {}", e);
+                    cache.set_listening(false);
+                    if !db.is_closed() {
+                        tokio::time::sleep(RETRY).await;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(module_id: Uuid, languages: &str) -> TreeKey {
+        TreeKey {
+            module_id,
+            languages: languages.to_owned(),
+            include: String::new(),
+        }
+    }
+
+    #[test]
+    fn invalidates_trees() {
+        let cache = TreeCache::new(2);
+        let (m1, m2) = (Uuid::new_v4(), Uuid::new_v4());
+        let json = Bytes::from_static(b"[]");
+
+        // not listening
+        cache.insert(
+            key(m1, ""),
+            cache.generation(m1),
+            "\"1\"".to_owned(),
+            json.clone(),
+        );
+        assert!(cache.get(&key(m1, "")).is_none());
+
+        cache.set_listening(true);
+        cache.insert(
+            key(m1, ""),
+            cache.generation(m1),
+            "\"1\"".to_owned(),
+            json.clone(),
+        );
+        cache.insert(
+            key(m2, ""),
+            cache.generation(m2),
+            "\"2\"".to_owned(),
+            json.clone(),
+        );
+        assert_eq!(cache.get(&key(m1, "")).unwrap().etag, "\"1\"");
+
+        // changed while building
+        let generation = cache.generation(m1);
+        cache.invalidate(m1);
+        assert!(cache.get(&key(m1, "")).is_none());
+        cache.insert(key(m1, "en"), generation, "\"3\"".to_owned(), json.clone());
+        assert!(cache.get(&key(m1, "en")).is_none());
+        assert!(cache.get(&key(m2, "")).is_some());
+
+        // full, the oldest is evicted
+        cache.insert(
+            key(m1, ""),
+            cache.generation(m1),
+            "\"4\"".to_owned(),
+            json.clone(),
+        );
+        cache.insert(
+            key(m1, "en"),
+            cache.generation(m1),
+            "\"5\"".to_owned(),
+            json.clone(),
+        );
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key(m2, "")).is_none());
+
+        // missed notifications
+        let generation = cache.generation(m2);
+        cache.set_listening(false);
+        cache.set_listening(true);
+        assert_eq!(cache.len(), 0);
+        cache.insert(key(m2, ""), generation, "\"6\"".to_owned(), json);
+        assert!(cache.get(&key(m2, "")).is_none());
+    }
+}